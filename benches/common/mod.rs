@@ -0,0 +1,18 @@
+//! Input shared by the benches
+
+/// Lines of a Cargo.toml, mostly LF with a CRLF one, repeated up to `len` bytes
+pub fn input(len: usize) -> String {
+    let lines = [
+        "[dependencies]\n",
+        "clap = { version = \"4.5.20\", features = [\"derive\"] }\r\n",
+        "\n",
+    ];
+    let mut input = String::with_capacity(len);
+    for line in lines.iter().cycle() {
+        if input.len() + line.len() > len {
+            break;
+        }
+        input.push_str(line);
+    }
+    input
+}
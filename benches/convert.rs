@@ -0,0 +1,36 @@
+//! Compare [`convert_str`] against [`normalize_string_in_place`]
+//!
+//! Run with `cargo bench --bench convert`.
+
+mod common;
+
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use crlf::{convert_str, normalize_string_in_place, LineEnding};
+
+const INPUT_LEN: usize = 16 * 1024 * 1024;
+
+fn convert(c: &mut Criterion) {
+    let input = common::input(INPUT_LEN);
+    let mut group = c.benchmark_group("convert");
+    group.throughput(Throughput::Bytes(input.len() as u64));
+    group.sample_size(20);
+    for ending in [LineEnding::LF, LineEnding::CRLF] {
+        group.bench_function(format!("convert_str {ending}"), |b| {
+            b.iter(|| convert_str(black_box(&input), ending))
+        });
+        // the copy to convert isn't timed
+        group.bench_function(format!("in_place {ending}"), |b| {
+            b.iter_batched_ref(
+                || input.clone(),
+                |text| normalize_string_in_place(text, ending),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, convert);
+criterion_main!(benches);
@@ -0,0 +1,36 @@
+//! Compare copying every line against [`CrlfStat::measure_fast`]
+//!
+//! Run with `cargo bench --bench measure`, add `--features memchr` to see
+//! the SIMD search of memchr, which [`CrlfStat::measure_file`] then uses too.
+
+mod common;
+
+use std::hint::black_box;
+use std::io::BufReader;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use crlf::CrlfStat;
+
+const INPUT_LEN: usize = 16 * 1024 * 1024;
+
+fn measure(c: &mut Criterion) {
+    let input = common::input(INPUT_LEN).into_bytes();
+    let mut group = c.benchmark_group("measure");
+    group.throughput(Throughput::Bytes(input.len() as u64));
+    group.sample_size(20);
+    group.bench_function("lines", |b| {
+        b.iter(|| {
+            CrlfStat::measure_file_with_buf(BufReader::new(black_box(&input[..])), &mut vec![])
+        })
+    });
+    group.bench_function("measure_file", |b| {
+        b.iter(|| CrlfStat::measure_file(BufReader::new(black_box(&input[..]))))
+    });
+    group.bench_function("measure_fast", |b| {
+        b.iter(|| CrlfStat::measure_fast(BufReader::new(black_box(&input[..]))))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, measure);
+criterion_main!(benches);
@@ -0,0 +1,178 @@
+//! The streaming functions on the async IO traits of tokio
+//!
+//! Only the reading and writing await, the lines are measured and converted
+//! by the same code as [`CrlfStat::measure_fast`] and [`crate::convert_with`].
+
+use std::io::ErrorKind;
+
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::{ConvertOptions, CrlfStat, FastScan, LineConverter, LineEnding, CR, LF};
+
+/// Same as [`CrlfStat::measure_file`] for an async `source`
+pub async fn measure_file_async<R: AsyncBufRead + Unpin>(
+    mut source: R,
+) -> std::io::Result<CrlfStat> {
+    let mut scan = FastScan::default();
+    loop {
+        let block = match source.fill_buf().await {
+            Ok(block) => block,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        if block.is_empty() {
+            break;
+        }
+        scan.block(block);
+        let len = block.len();
+        source.consume(len);
+    }
+    Ok(scan.finish())
+}
+
+/// Same as [`crate::convert_to`] for an async `source` and `dest`
+pub async fn convert_to_async<R: AsyncBufRead + Unpin, W: AsyncWrite + Unpin>(
+    source: R,
+    dest: W,
+    ending: LineEnding,
+) -> std::io::Result<CrlfStat> {
+    convert_with_async(source, dest, &ConvertOptions::new(ending)).await
+}
+
+/// Same as [`crate::convert_with`] for an async `source` and `dest`
+///
+/// Every line is converted into a buffer first, then written to `dest`.
+pub async fn convert_with_async<R: AsyncBufRead + Unpin, W: AsyncWrite + Unpin>(
+    mut source: R,
+    mut dest: W,
+    options: &ConvertOptions,
+) -> std::io::Result<CrlfStat> {
+    let mut buf = Vec::with_capacity(options.line_capacity);
+    let mut converted = Vec::with_capacity(options.line_capacity);
+    let mut converter = LineConverter::default();
+    while read_line(&mut source, &mut buf).await? != 0 {
+        converter.line(&buf, &mut converted, options, &mut |_, _| options.ending)?;
+        dest.write_all(&converted).await?;
+        buf.clear();
+        converted.clear();
+    }
+    let stat = converter.finish(&mut converted, options)?;
+    dest.write_all(&converted).await?;
+    dest.flush().await?;
+    Ok(stat)
+}
+
+/// Same as [`crate::read_line`] for an async `source`
+async fn read_line<R: AsyncBufRead + Unpin>(
+    source: &mut R,
+    buf: &mut Vec<u8>,
+) -> std::io::Result<usize> {
+    let start = buf.len();
+    let mut after_cr = false;
+    loop {
+        let available = match source.fill_buf().await {
+            Ok(available) => available,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        if after_cr {
+            // a CR followed by a LF is a single CRLF ending
+            if available.first() == Some(&LF) {
+                buf.push(LF);
+                source.consume(1);
+            }
+            break;
+        }
+        if available.is_empty() {
+            break;
+        }
+        match available.iter().position(|c| *c == LF || *c == CR) {
+            Some(idx) => {
+                let terminator = available[idx];
+                buf.extend_from_slice(&available[..=idx]);
+                source.consume(idx + 1);
+                if terminator == LF {
+                    break;
+                }
+                after_cr = true;
+            }
+            None => {
+                let len = available.len();
+                buf.extend_from_slice(available);
+                source.consume(len);
+            }
+        }
+    }
+    Ok(buf.len() - start)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{convert_with, Bom};
+    use std::future::Future;
+    use std::io::Cursor;
+    use std::task::{Context, Poll, Waker};
+
+    /// Run a future that never waits, reading and writing memory never does
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = std::pin::pin!(future);
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+        }
+    }
+
+    /// Reads `bytes` in blocks of `size`, so lines and CRLF are split between reads
+    fn chunked(bytes: &[u8], size: usize) -> tokio::io::BufReader<&[u8]> {
+        tokio::io::BufReader::with_capacity(size, bytes)
+    }
+
+    #[test]
+    fn test_measure_async() {
+        let mut inputs = vec![
+            b"a\nb\r\nc\rd\r\re".to_vec(),
+            b"\xEF\xBB\xBFa\r\r\n\n\r".to_vec(),
+            vec![],
+        ];
+        for name in ["lf", "crlf", "cr", "mixed"] {
+            inputs.push(std::fs::read(format!("test/Cargo.toml.{name}")).unwrap());
+        }
+        for input in inputs {
+            let expected = CrlfStat::measure_fast(Cursor::new(&input)).unwrap();
+            for size in [1, 2, 7, 4096] {
+                let stat = block_on(measure_file_async(chunked(&input, size))).unwrap();
+                assert_eq!(stat.endings, expected.endings);
+                assert_eq!(stat.lines, expected.lines);
+                assert_eq!(stat.final_newline, expected.final_newline);
+                assert_eq!(stat.bom, expected.bom);
+            }
+        }
+    }
+
+    #[test]
+    fn test_convert_async() {
+        let mixed = std::fs::read("test/Cargo.toml.mixed").unwrap();
+        let mut dest = vec![];
+        let stat = block_on(convert_to_async(
+            chunked(&mixed, 3),
+            &mut dest,
+            LineEnding::LF,
+        ));
+        assert_eq!(stat.unwrap().crlf(), 8);
+        assert_eq!(dest, std::fs::read("test/Cargo.toml.lf").unwrap());
+
+        let input = b"\xEF\xBB\xBFa  \r\nb\rc".as_slice();
+        let options = ConvertOptions::new(LineEnding::CRLF)
+            .strip_trailing_ws(true)
+            .ensure_final_newline(true)
+            .bom(Bom::Strip);
+        let mut expected = vec![];
+        convert_with(input, &mut expected, &options).unwrap();
+        let mut dest = vec![];
+        block_on(convert_with_async(chunked(input, 1), &mut dest, &options)).unwrap();
+        assert_eq!(dest, expected);
+    }
+}
@@ -0,0 +1,90 @@
+//! Line endings declared by the project for its files
+
+use crate::discovery;
+use anyhow::{anyhow, Context};
+use crlf::LineEnding;
+use std::{
+    collections::HashMap,
+    io::Write,
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+
+/// What a project declares about the line ending of one file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Declared {
+    Ending(LineEnding),
+    /// The file isn't text and must be left alone
+    NotText,
+}
+
+/// The `eol` and `text` attributes of `files` as git sees them
+///
+/// Files without an `eol` attribute, including `text=auto` ones whose ending
+/// depends on the git config, are missing from the result.
+pub fn gitattributes(
+    git: &discovery::Git,
+    files: &[PathBuf],
+) -> anyhow::Result<HashMap<PathBuf, Declared>> {
+    let mut child = git
+        .command()
+        .args(["check-attr", "-z", "--stdin", "eol", "text"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .context("Run git command failed")?;
+    let mut list = vec![];
+    for f in files {
+        list.extend_from_slice(f.as_os_str().as_encoded_bytes());
+        list.push(0);
+    }
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    // git may fill the stdout pipe before all paths are written
+    let writer = std::thread::spawn(move || stdin.write_all(&list));
+    let output = child.wait_with_output().context("Run git command failed")?;
+    writer
+        .join()
+        .expect("writing to git doesn't panic")
+        .context("Write to git command failed")?;
+    if !output.status.success() {
+        return Err(anyhow!("git check-attr failed, is this a git repository?"));
+    }
+
+    // every entry is `path NUL attribute NUL value NUL`
+    let fields: Vec<&[u8]> = output.stdout.split(|c| *c == 0).collect();
+    let mut declared = HashMap::new();
+    for entry in fields.chunks_exact(3) {
+        let path = discovery::path_from_bytes(entry[0]);
+        match (entry[1], entry[2]) {
+            (b"text", b"unset") => {
+                declared.insert(path, Declared::NotText);
+            }
+            (b"eol", b"lf") => {
+                declared
+                    .entry(path)
+                    .or_insert(Declared::Ending(LineEnding::LF));
+            }
+            (b"eol", b"crlf") => {
+                declared
+                    .entry(path)
+                    .or_insert(Declared::Ending(LineEnding::CRLF));
+            }
+            _ => {}
+        }
+    }
+    Ok(declared)
+}
+
+/// The `end_of_line` property of `f`, from every .editorconfig above it
+pub fn editorconfig(f: &Path) -> anyhow::Result<Option<Declared>> {
+    use ec4rs::property::EndOfLine;
+
+    let properties = ec4rs::properties_of(f)
+        .context(format!("Read .editorconfig for {} failed", f.display()))?;
+    Ok(match properties.get::<EndOfLine>() {
+        Ok(EndOfLine::Lf) => Some(Declared::Ending(LineEnding::LF)),
+        Ok(EndOfLine::CrLf) => Some(Declared::Ending(LineEnding::CRLF)),
+        Ok(EndOfLine::Cr) => Some(Declared::Ending(LineEnding::CR)),
+        Err(_) => None,
+    })
+}
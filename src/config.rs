@@ -0,0 +1,81 @@
+//! Project defaults read from a `.crlf.toml`
+//!
+//! ```toml
+//! ending = "lf"
+//! patterns = ["src/**/*"]
+//! exclude = ["**/*.bat"]
+//! use_gitattributes = true
+//! ```
+//!
+//! The patterns and excludes are relative to the directory of the config file,
+//! wherever in the tree the tool is run from.
+
+use anyhow::Context;
+use crlf::LineEnding;
+use std::path::{Path, PathBuf};
+
+pub const FILE_NAME: &str = ".crlf.toml";
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Line ending used when the command line leaves it open, like `--prefer`
+    /// and by `set` without an ending
+    #[serde(deserialize_with = "ending")]
+    pub ending: Option<LineEnding>,
+    /// Patterns used when none is given on the command line
+    pub patterns: Vec<String>,
+    /// Added to the `--exclude` patterns
+    pub exclude: Vec<String>,
+    pub respect_gitignore: bool,
+    pub use_gitattributes: bool,
+    pub use_editorconfig: bool,
+}
+
+fn ending<'de, D: serde::Deserializer<'de>>(d: D) -> Result<Option<LineEnding>, D::Error> {
+    let ending: String = serde::Deserialize::deserialize(d)?;
+    ending.parse().map(Some).map_err(serde::de::Error::custom)
+}
+
+/// The nearest config file in `dir` or one of its parents
+pub fn find(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|dir| dir.join(FILE_NAME))
+        .find(|path| path.is_file())
+}
+
+/// Read the config at `path` and resolve its patterns for running in `cwd`
+pub fn load(path: &Path, cwd: &Path) -> anyhow::Result<Config> {
+    let text =
+        std::fs::read_to_string(path).context(format!("Read config {} failed", path.display()))?;
+    let mut config: Config =
+        toml::from_str(&text).context(format!("Parse config {} failed", path.display()))?;
+    config.resolve(path.parent().unwrap_or(Path::new("")), cwd);
+    Ok(config)
+}
+
+impl Config {
+    /// Rebase the patterns from the config directory `dir` onto `cwd`
+    ///
+    /// The patterns stay relative so the found paths are printed like those of
+    /// the command line patterns. The excludes become absolute, they match a
+    /// file however its path was written.
+    fn resolve(&mut self, dir: &Path, cwd: &Path) {
+        let escaped = glob::Pattern::escape(&dir.to_string_lossy());
+        let escaped = escaped.trim_end_matches(['/', '\\']);
+        let prefix = match cwd.strip_prefix(dir) {
+            Ok(below) => "../".repeat(below.components().count()),
+            Err(_) => format!("{escaped}/"),
+        };
+        for pattern in &mut self.patterns {
+            if !Path::new(pattern).is_absolute() {
+                *pattern = format!("{prefix}{pattern}");
+            }
+        }
+        for exclude in &mut self.exclude {
+            if !Path::new(exclude).is_absolute() {
+                *exclude = format!("{escaped}/{exclude}");
+            }
+        }
+    }
+}
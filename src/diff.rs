@@ -0,0 +1,84 @@
+//! Unified diffs of conversions with the line endings made visible
+//!
+//! A diff of a pure line ending change looks like nothing changed, so CR, LF
+//! and tab are rendered as the control pictures ␍, ␊ and ␉ before diffing.
+
+use ansi_term::Color;
+use std::path::Path;
+
+/// Diff of `path` changing from `old` to `new`, empty when they're equal
+pub fn unified(path: &Path, old: &[u8], new: &[u8], color: bool) -> String {
+    let old = render(&decode(old));
+    let new = render(&decode(new));
+    let old: Vec<&str> = old.iter().map(String::as_str).collect();
+    let new: Vec<&str> = new.iter().map(String::as_str).collect();
+    let diff = similar::TextDiff::configure().diff_slices(&old, &new);
+    let name = path.display().to_string();
+    let text = diff.unified_diff().header(&name, &name).to_string();
+    if !color {
+        return text;
+    }
+    let mut painted = String::with_capacity(text.len());
+    for line in text.lines() {
+        let colour = match line.as_bytes() {
+            [b'-', b'-', b'-', ..] | [b'+', b'+', b'+', ..] => None,
+            [b'-', ..] => Some(Color::Red),
+            [b'+', ..] => Some(Color::Green),
+            [b'@', ..] => Some(Color::Cyan),
+            _ => None,
+        };
+        match colour {
+            Some(colour) => painted.push_str(&colour.paint(line).to_string()),
+            None => painted.push_str(line),
+        }
+        painted.push('\n');
+    }
+    painted
+}
+
+/// Text of a file, UTF-16 files are recognized by their byte order mark
+fn decode(bytes: &[u8]) -> String {
+    let units = |to_unit: fn([u8; 2]) -> u16| {
+        let units: Vec<u16> = bytes
+            .chunks(2)
+            .map(|unit| to_unit([unit[0], unit.get(1).copied().unwrap_or_default()]))
+            .collect();
+        String::from_utf16_lossy(&units)
+    };
+    match crlf::utf16::detect_bom(bytes) {
+        Some(crlf::utf16::ByteOrder::LittleEndian) => units(u16::from_le_bytes),
+        Some(crlf::utf16::ByteOrder::BigEndian) => units(u16::from_be_bytes),
+        None => String::from_utf8_lossy(bytes).into_owned(),
+    }
+}
+
+/// Lines of `text` with the control characters replaced by their pictures
+fn render(text: &str) -> Vec<String> {
+    let mut lines = vec![];
+    let mut line = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        let end = match c {
+            '\r' if chars.peek() == Some(&'\n') => {
+                chars.next();
+                "␍␊"
+            }
+            '\r' => "␍",
+            '\n' => "␊",
+            '\t' => {
+                line.push('␉');
+                continue;
+            }
+            c => {
+                line.push(c);
+                continue;
+            }
+        };
+        line.push_str(end);
+        lines.push(std::mem::take(&mut line));
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
@@ -0,0 +1,596 @@
+//! Find the files to work on
+
+use anyhow::{anyhow, Context};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::{
+    collections::{HashMap, HashSet},
+    io::Read,
+    path::{Component, Path, PathBuf},
+};
+
+/// How git is run, the binary and options put before every git command
+#[derive(Debug, Clone)]
+pub struct Git {
+    bin: PathBuf,
+    args: Vec<String>,
+    /// Where git runs, the current directory when `None`
+    dir: Option<PathBuf>,
+}
+
+/// Options moving git elsewhere or sending its output to a pager,
+/// the paths it prints wouldn't be relative to the current directory anymore
+const GIT_REJECTED_ARGS: [&str; 5] = ["-C", "-p", "--paginate", "--git-dir", "--work-tree"];
+
+impl Git {
+    /// Only options of git itself are taken in `args`, like `-c core.quotepath=false`
+    pub fn new(bin: &Path, args: &[String]) -> anyhow::Result<Self> {
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            let name = arg.split_once('=').map_or(arg.as_str(), |(name, _)| name);
+            if GIT_REJECTED_ARGS.contains(&name) {
+                return Err(anyhow!(
+                    "--git-arg {arg} changes the paths git prints, which are read as relative to the current directory"
+                ));
+            }
+            if !arg.starts_with('-') {
+                return Err(anyhow!(
+                    "--git-arg {arg} isn't an option, only options of git go before its command"
+                ));
+            }
+            if arg == "-c" {
+                iter.next();
+            }
+        }
+        Ok(Self {
+            bin: bin.to_path_buf(),
+            args: args.to_vec(),
+            dir: None,
+        })
+    }
+
+    /// Run git in `dir`, for the tests that can't change the current directory
+    #[cfg(test)]
+    fn in_dir(mut self, dir: &Path) -> Self {
+        self.dir = Some(dir.to_path_buf());
+        self
+    }
+
+    /// A git command still missing the git subcommand and its arguments
+    pub fn command(&self) -> std::process::Command {
+        let mut command = std::process::Command::new(&self.bin);
+        command.args(&self.args);
+        if let Some(dir) = &self.dir {
+            command.current_dir(dir);
+        }
+        command
+    }
+
+    /// Text files known to git, `patterns` are passed to git grep as pathspecs
+    ///
+    /// The names are read NUL separated, git quotes names with unusual characters otherwise.
+    /// Untracked files are listed too, unless `tracked_only`.
+    pub fn files(
+        &self,
+        patterns: &[String],
+        ignore_case: bool,
+        tracked_only: bool,
+    ) -> anyhow::Result<Vec<PathBuf>> {
+        let git_result = self
+            .command()
+            // the color of a grep.color config would end up in the names
+            .args(["grep", "-I", "--name-only", "--no-color"])
+            .args((!tracked_only).then_some("--untracked"))
+            .args(["-z", "-e", ".", "--"])
+            .args(patterns.iter().map(|p| {
+                let p = if p == "**/*" { "*" } else { p.as_str() };
+                if ignore_case {
+                    format!(":(icase){p}")
+                } else {
+                    p.to_string()
+                }
+            }))
+            .output()
+            .context("Run git command failed")?;
+        if !git_result.status.success() {
+            if let Some(code) = git_result.status.code() {
+                return Err(anyhow!("Git command failed with exit code: {code}"));
+            } else {
+                return Err(anyhow!("git exit unexpectly without an exit code"));
+            }
+        }
+        Ok(parse_file_list(&git_result.stdout, true))
+    }
+
+    /// Top directory of the git repository containing the current directory
+    pub fn toplevel(&self) -> anyhow::Result<PathBuf> {
+        let output = self
+            .command()
+            .args(["rev-parse", "--show-toplevel"])
+            .output()
+            .context("Run git command failed")?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "Find the git repository failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        let toplevel = output.stdout.strip_suffix(b"\n").unwrap_or(&output.stdout);
+        Ok(path_from_bytes(toplevel))
+    }
+}
+
+/// Paths read from stdin, one per line or NUL separated with `null`
+pub fn stdin_files(null: bool) -> anyhow::Result<Vec<PathBuf>> {
+    let mut list = vec![];
+    std::io::stdin()
+        .read_to_end(&mut list)
+        .context("Read file list from stdin failed")?;
+    Ok(parse_file_list(&list, null))
+}
+
+/// Split a list of paths on newlines, or on NUL with `null`
+///
+/// Newline separated paths are trimmed, NUL separated ones are taken as is
+/// since they're meant for names with unusual characters. Empty entries are dropped.
+fn parse_file_list(list: &[u8], null: bool) -> Vec<PathBuf> {
+    let separator = if null { b'\0' } else { b'\n' };
+    list.split(|c| *c == separator)
+        .map(|s| if null { s } else { s.trim_ascii() })
+        .filter(|s| !s.is_empty())
+        .map(path_from_bytes)
+        .collect()
+}
+
+#[cfg(unix)]
+pub fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+}
+
+#[cfg(not(unix))]
+pub fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).as_ref())
+}
+
+/// Options for matching the glob patterns, `ignore_case` matches `*.TXT` with `file.txt`
+///
+/// Wildcards only match a leading dot with `include_hidden`, so `**/*` leaves
+/// out `.env` and everything in `.git`. A pattern spelling out the dot,
+/// like `.gitignore`, is always matched.
+pub fn match_options(ignore_case: bool, include_hidden: bool) -> glob::MatchOptions {
+    glob::MatchOptions {
+        case_sensitive: !ignore_case,
+        require_literal_leading_dot: !include_hidden,
+        ..Default::default()
+    }
+}
+
+/// Regular files matching any of the glob `patterns`, in the order they're found
+///
+/// A pattern naming an existing file is taken as that path, even if it looks
+/// like a glob, like `[draft].txt`. A pattern naming an existing directory
+/// stands for every file below it. The same file is only listed once.
+/// With `max_depth`, files nested deeper than that below the directory the
+/// pattern starts from are dropped, see [`depth`].
+pub fn glob_files(
+    patterns: &[String],
+    max_depth: Option<usize>,
+    options: glob::MatchOptions,
+) -> anyhow::Result<Vec<PathBuf>> {
+    glob_walk(patterns, max_depth, options).collect()
+}
+
+/// Same as [`glob_files`], yielding every file as soon as the walk reaches it
+///
+/// A pattern failing to parse or a directory failing to read is yielded as an error.
+pub fn glob_walk(
+    patterns: &[String],
+    max_depth: Option<usize>,
+    options: glob::MatchOptions,
+) -> impl Iterator<Item = anyhow::Result<PathBuf>> + '_ {
+    let mut seen = HashSet::new();
+    patterns
+        .iter()
+        .flat_map(move |pattern| pattern_files(pattern, max_depth, options))
+        .filter(move |f| f.as_ref().map_or(true, |f| seen.insert(without_cur_dir(f))))
+}
+
+/// The files of one pattern of [`glob_walk`], maybe listed more than once
+fn pattern_files(
+    pattern: &str,
+    max_depth: Option<usize>,
+    options: glob::MatchOptions,
+) -> Box<dyn Iterator<Item = anyhow::Result<PathBuf>>> {
+    let literal = Path::new(pattern);
+    if literal.is_file() {
+        return Box::new(std::iter::once(Ok(literal.to_path_buf())));
+    }
+    let (pattern, base) = if literal.is_dir() {
+        let dir = pattern.trim_end_matches(['/', '\\']);
+        (
+            format!("{}/**/*", glob::Pattern::escape(dir)),
+            PathBuf::from(dir),
+        )
+    } else {
+        (pattern.to_string(), literal_prefix(pattern))
+    };
+    let paths = match glob::glob_with(&pattern, options) {
+        Ok(paths) => paths,
+        Err(e) => {
+            let e = anyhow::Error::new(e).context(format!("Failed to read glob pattern {pattern}"));
+            return Box::new(std::iter::once(Err(e)));
+        }
+    };
+    Box::new(paths.filter_map(move |f| {
+        let f = match f.context("Glob match error") {
+            Ok(f) => f,
+            Err(e) => return Some(Err(e)),
+        };
+        // glob_with lets `**` walk into hidden directories whatever the options
+        if options.require_literal_leading_dot && hidden_by_wildcard(&f, &base, &pattern) {
+            return None;
+        }
+        if max_depth.is_some_and(|max| depth(&f, &base) > max) {
+            return None;
+        }
+        f.is_file().then_some(Ok(f))
+    }))
+}
+
+/// `f` without its `.` components, so `./a.txt` and `a.txt` are seen as the same file
+fn without_cur_dir(f: &Path) -> PathBuf {
+    f.components().filter(|c| *c != Component::CurDir).collect()
+}
+
+/// Whether a hidden component of `f` below `base` isn't matched by a
+/// component of `pattern` starting with a dot, so only a wildcard matched it
+fn hidden_by_wildcard(f: &Path, base: &Path, pattern: &str) -> bool {
+    // the pattern components starting with a literal dot, like `.github` or `.*`
+    let dotted: Vec<glob::Pattern> = pattern
+        .split(['/', '\\'])
+        .filter(|c| c.starts_with('.'))
+        .filter_map(|c| glob::Pattern::new(c).ok())
+        .collect();
+    f.strip_prefix(base)
+        .unwrap_or(f)
+        .components()
+        .any(|c| match c {
+            Component::Normal(name) => {
+                let name = name.to_string_lossy();
+                name.starts_with('.') && !dotted.iter().any(|p| p.matches(&name))
+            }
+            _ => false,
+        })
+}
+
+/// Leading components of a glob pattern without any wildcard
+fn literal_prefix(pattern: &str) -> PathBuf {
+    Path::new(pattern)
+        .components()
+        .take_while(|c| !c.as_os_str().to_string_lossy().contains(['*', '?', '[']))
+        .collect()
+}
+
+/// Drop the files nested deeper than `max_depth` below the current directory
+pub fn limit_depth(files: &mut Vec<PathBuf>, max_depth: Option<usize>) {
+    if let Some(max) = max_depth {
+        files.retain(|f| depth(f, Path::new("")) <= max);
+    }
+}
+
+/// Number of directories between `base` and the file `f`
+///
+/// Files directly in `base` are at depth 0.
+fn depth(f: &Path, base: &Path) -> usize {
+    let relative = f.strip_prefix(base).unwrap_or(f);
+    relative.components().count().saturating_sub(1)
+}
+
+/// Drop the files matching any of the `excludes` glob patterns
+pub fn exclude(
+    files: &mut Vec<PathBuf>,
+    excludes: &[String],
+    options: glob::MatchOptions,
+) -> anyhow::Result<()> {
+    let excludes = exclude_patterns(excludes)?;
+    files.retain(|f| !is_excluded(f, &excludes, options));
+    Ok(())
+}
+
+pub fn exclude_patterns(excludes: &[String]) -> anyhow::Result<Vec<glob::Pattern>> {
+    excludes
+        .iter()
+        .map(|e| glob::Pattern::new(e).context(format!("Invalid exclude pattern {e}")))
+        .collect()
+}
+
+/// Whether `f` matches one of the `excludes`, as written or as an absolute path
+pub fn is_excluded(f: &Path, excludes: &[glob::Pattern], options: glob::MatchOptions) -> bool {
+    if excludes.is_empty() {
+        return false;
+    }
+    let absolute = std::path::absolute(f).map(|f| lexical(&f));
+    excludes.iter().any(|e| {
+        e.matches_path_with(f, options)
+            || absolute
+                .as_ref()
+                .is_ok_and(|f| e.matches_path_with(f, options))
+    })
+}
+
+/// Drop symlinks from `files`, or resolve them with `follow`
+///
+/// Converting a file through a symlink rewrites its target, which may live
+/// outside the tree being processed. When following, each link is replaced
+/// by its canonical target and a target listed more than once is kept once.
+pub fn symlinks(files: &mut Vec<PathBuf>, follow: bool) {
+    let mut symlinks = Symlinks::new(follow);
+    let kept = files
+        .drain(..)
+        .filter_map(|f| symlinks.resolve(f))
+        .collect();
+    *files = kept;
+}
+
+/// Same as [`symlinks`] one file at a time, remembering the files already seen
+pub struct Symlinks {
+    follow: bool,
+    seen: HashSet<PathBuf>,
+}
+
+impl Symlinks {
+    pub fn new(follow: bool) -> Self {
+        Self {
+            follow,
+            seen: HashSet::new(),
+        }
+    }
+
+    /// The path to process for `f`, `None` to leave it out
+    pub fn resolve(&mut self, f: PathBuf) -> Option<PathBuf> {
+        // paths that can't be resolved are kept, reading them reports the error
+        let is_symlink = f.symlink_metadata().is_ok_and(|m| m.is_symlink());
+        if is_symlink && !self.follow {
+            return None;
+        }
+        let Ok(canonical) = f.canonicalize() else {
+            return Some(f);
+        };
+        if is_symlink {
+            (canonical.is_file() && self.seen.insert(canonical.clone())).then_some(canonical)
+        } else {
+            self.seen.insert(canonical).then_some(f)
+        }
+    }
+}
+
+/// Rewrite `files` relative to `cwd`, or as absolute paths with `absolute`
+///
+/// Only the text of the paths is changed, so `..` isn't resolved through symlinks.
+/// A path on another drive than `cwd` has no relative form and stays absolute.
+pub fn rewrite_paths(files: &mut [PathBuf], cwd: &Path, absolute: bool) {
+    for f in files.iter_mut() {
+        let full = lexical(&cwd.join(&*f));
+        *f = if absolute {
+            full
+        } else {
+            relative_to(&full, &lexical(cwd)).unwrap_or(full)
+        };
+    }
+}
+
+/// `path` without `.` components and with every `..` applied to the one before it
+fn lexical(path: &Path) -> PathBuf {
+    let mut result = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(result.components().next_back(), Some(Component::Normal(_))) =>
+            {
+                result.pop();
+            }
+            component => result.push(component),
+        }
+    }
+    result
+}
+
+fn relative_to(path: &Path, base: &Path) -> Option<PathBuf> {
+    let mut path_components = path.components().peekable();
+    let mut base_components = base.components().peekable();
+    if path_components.peek() != base_components.peek() {
+        return None;
+    }
+    while path_components.peek().is_some() && path_components.peek() == base_components.peek() {
+        path_components.next();
+        base_components.next();
+    }
+    let mut result: PathBuf = base_components.map(|_| Component::ParentDir).collect();
+    result.extend(path_components);
+    Some(result)
+}
+
+/// Tells whether git would ignore a path
+///
+/// Every `.gitignore` from the file's directory up to the repository root
+/// is consulted, plus `.git/info/exclude`. Parsed files are cached per directory.
+#[derive(Default)]
+pub struct GitignoreFilter {
+    cache: HashMap<PathBuf, Option<Gitignore>>,
+}
+
+impl GitignoreFilter {
+    pub fn is_ignored(&mut self, path: &Path) -> anyhow::Result<bool> {
+        let path =
+            std::path::absolute(path).context(format!("Resolve path {} failed", path.display()))?;
+        if path.components().any(|c| c.as_os_str() == ".git") {
+            return Ok(true);
+        }
+
+        for dir in path.ancestors().skip(1) {
+            if let Some(gitignore) = self.gitignore(dir)? {
+                let matched = gitignore.matched_path_or_any_parents(&path, false);
+                if matched.is_ignore() {
+                    return Ok(true);
+                }
+                if matched.is_whitelist() {
+                    return Ok(false);
+                }
+            }
+            if is_repo_root(dir) {
+                break;
+            }
+        }
+        Ok(false)
+    }
+
+    fn gitignore(&mut self, dir: &Path) -> anyhow::Result<&Option<Gitignore>> {
+        if !self.cache.contains_key(dir) {
+            let gitignore = load_gitignore(dir)?;
+            self.cache.insert(dir.to_path_buf(), gitignore);
+        }
+        Ok(&self.cache[dir])
+    }
+}
+
+fn is_repo_root(dir: &Path) -> bool {
+    dir.join(".git").exists()
+}
+
+fn load_gitignore(dir: &Path) -> anyhow::Result<Option<Gitignore>> {
+    let mut builder = GitignoreBuilder::new(dir);
+    let mut found = false;
+    // later rules take precedence, so the exclude file comes first
+    let mut sources = vec![];
+    if is_repo_root(dir) {
+        sources.push(dir.join(".git").join("info").join("exclude"));
+    }
+    sources.push(dir.join(".gitignore"));
+    for source in sources.iter().filter(|s| s.is_file()) {
+        if let Some(e) = builder.add(source) {
+            return Err(e).context(format!("Parse {} failed", source.display()));
+        }
+        found = true;
+    }
+    if !found {
+        return Ok(None);
+    }
+    Ok(Some(builder.build().context(format!(
+        "Parse ignore files in {} failed",
+        dir.display()
+    ))?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    // a tab isn't allowed in a file name on Windows
+    #[cfg(unix)]
+    #[test]
+    fn test_git_files_unusual_names() {
+        if std::process::Command::new("git")
+            .arg("--version")
+            .output()
+            .is_err()
+        {
+            eprintln!("git not found, test_git_files_unusual_names skipped");
+            return;
+        }
+        let dir = TestDir::new("git");
+        let git = Git::new(Path::new("git"), &[]).unwrap().in_dir(&dir);
+        let init = git.command().args(["init", "-q"]).status().unwrap();
+        assert!(init.success());
+        for name in ["é.txt", "a b.txt", "tab\t.txt"] {
+            std::fs::write(dir.join(name), "a\n").unwrap();
+        }
+        let mut files = git.files(&["**/*".to_string()], false, false).unwrap();
+        files.sort();
+        assert_eq!(files, ["a b.txt", "tab\t.txt", "é.txt"].map(PathBuf::from));
+    }
+
+    #[test]
+    fn test_hidden_by_wildcard() {
+        let hidden = |f: &str, pattern: &str| {
+            hidden_by_wildcard(Path::new(f), &literal_prefix(pattern), pattern)
+        };
+        assert!(hidden(".git/config", "**/*"));
+        assert!(hidden("src/.env", "src/*"));
+        assert!(!hidden("src/main.rs", "**/*"));
+
+        // a component spelling out the dot matches it
+        assert!(!hidden(".github/x", ".github/*"));
+        assert!(!hidden(".github/x", ".github/**/*"));
+        assert!(hidden(".github/.x", ".github/*"));
+        assert!(!hidden(".env", ".*"));
+        assert!(!hidden("src/.env", "src/.*"));
+
+        // the hidden directory the pattern starts from isn't matched by a wildcard
+        assert!(!hidden(".config/a/b", ".config/**/*"));
+        assert!(hidden(".config/a/.b", ".config/**/*"));
+        assert!(!hidden(".config/a", ".config"));
+    }
+
+    #[test]
+    fn test_gitignore_filter() {
+        let dir = TestDir::new("gitignore");
+        std::fs::create_dir_all(dir.join(".git").join("info")).unwrap();
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join(".gitignore"), "*.log\n!keep.log\n*.tmp\n").unwrap();
+        std::fs::write(dir.join("sub").join(".gitignore"), "!b.tmp\nlocal.txt\n").unwrap();
+        std::fs::write(dir.join(".git").join("info").join("exclude"), "*.bak\n").unwrap();
+
+        let mut filter = GitignoreFilter::default();
+        let mut ignored = |f: &str| filter.is_ignored(&dir.join(f)).unwrap();
+        assert!(ignored("a.log"));
+        assert!(!ignored("keep.log"));
+        assert!(ignored("a.tmp"));
+        assert!(ignored("a.bak"));
+        assert!(!ignored("a.txt"));
+        assert!(ignored(".git/config"));
+        // the nearest .gitignore decides, and it can take back a parent's rule
+        assert!(!ignored("sub/b.tmp"));
+        assert!(ignored("sub/c.tmp"));
+        assert!(ignored("sub/local.txt"));
+        assert!(!ignored("local.txt"));
+        assert!(ignored("sub/keep.bak"));
+    }
+
+    #[test]
+    fn test_max_depth() {
+        assert_eq!(depth(Path::new("a.txt"), Path::new("")), 0);
+        assert_eq!(depth(Path::new("src/a/b.rs"), Path::new("")), 2);
+        assert_eq!(depth(Path::new("src/a/b.rs"), Path::new("src")), 1);
+
+        let mut files = ["a.txt", "src/b.rs", "src/c/d.rs"]
+            .map(PathBuf::from)
+            .to_vec();
+        limit_depth(&mut files, None);
+        assert_eq!(files.len(), 3);
+        limit_depth(&mut files, Some(1));
+        assert_eq!(files, ["a.txt", "src/b.rs"].map(PathBuf::from));
+
+        let dir = TestDir::new("depth");
+        std::fs::create_dir_all(dir.join("sub").join("deep")).unwrap();
+        for f in ["a.txt", "sub/b.txt", "sub/deep/c.txt"] {
+            std::fs::write(dir.join(f), "a\n").unwrap();
+        }
+        let found = |pattern: PathBuf, max_depth| {
+            let pattern = pattern.display().to_string();
+            let mut files = glob_files(&[pattern], max_depth, match_options(false, false)).unwrap();
+            files.sort();
+            files
+        };
+        // counted from the directory the pattern starts from
+        assert_eq!(found(dir.to_path_buf(), Some(0)), [dir.join("a.txt")]);
+        assert_eq!(found(dir.join("sub"), Some(0)), [dir.join("sub/b.txt")]);
+        assert_eq!(
+            found(dir.join("sub/**/*.txt"), Some(1)),
+            [dir.join("sub/b.txt"), dir.join("sub/deep/c.txt")]
+        );
+        assert_eq!(found(dir.join("**/*.txt"), Some(1)).len(), 2);
+        assert_eq!(found(dir.join("**/*.txt"), None).len(), 3);
+    }
+}
@@ -0,0 +1,43 @@
+//! Errors of the library functions working on paths
+
+use std::path::PathBuf;
+
+/// Failure of a function working on a file, with the path of that file
+///
+/// The functions working on streams return [`std::io::Error`] instead,
+/// they don't know what the stream is.
+#[derive(Debug, thiserror::Error)]
+pub enum CrlfError {
+    /// The file couldn't be opened or read
+    #[error("read {} failed", path.display())]
+    Read {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    /// The file couldn't be created or written
+    #[error("write {} failed", path.display())]
+    Write {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    /// The file was read but its content isn't valid, like UTF-16 with an odd number of bytes
+    #[error("decode {} failed", path.display())]
+    Decode {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+impl CrlfError {
+    /// Path of the file that failed
+    pub fn path(&self) -> &std::path::Path {
+        match self {
+            CrlfError::Read { path, .. }
+            | CrlfError::Write { path, .. }
+            | CrlfError::Decode { path, .. } => path,
+        }
+    }
+}
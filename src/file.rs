@@ -0,0 +1,203 @@
+//! Convenience functions opening the files themselves
+//!
+//! UTF-16 files are recognized by their byte order mark, everything else is
+//! handled as bytes. Whether a file is text at all is up to the caller,
+//! see [`crate::is_binary`].
+
+use std::{
+    ffi::OsString,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{convert_to, utf16, CrlfError, CrlfStat, LineEnding};
+
+fn read_error(path: &Path) -> impl Fn(std::io::Error) -> CrlfError + '_ {
+    move |source| CrlfError::Read {
+        path: path.to_path_buf(),
+        source,
+    }
+}
+
+fn write_error(path: &Path) -> impl Fn(std::io::Error) -> CrlfError + '_ {
+    move |source| CrlfError::Write {
+        path: path.to_path_buf(),
+        source,
+    }
+}
+
+fn decode_error(path: &Path) -> impl Fn(std::io::Error) -> CrlfError + '_ {
+    move |source| CrlfError::Decode {
+        path: path.to_path_buf(),
+        source,
+    }
+}
+
+/// Same as [`CrlfStat::measure_fast`] for the file at `path`
+pub fn measure_path<P: AsRef<Path>>(path: P) -> Result<CrlfStat, CrlfError> {
+    let path = path.as_ref();
+    let mut source = BufReader::new(File::open(path).map_err(read_error(path))?);
+    let order = utf16::detect_bom(source.fill_buf().map_err(read_error(path))?);
+    match order {
+        Some(order) => utf16::measure_file(source, order),
+        None => CrlfStat::measure_fast(source),
+    }
+    .map_err(|e| match e.kind() {
+        // the odd byte at the end of UTF-16, reading the file never fails like that
+        ErrorKind::InvalidData if order.is_some() => decode_error(path)(e),
+        _ => read_error(path)(e),
+    })
+}
+
+/// Convert the file at `source` to `ending`, writing the result to `dest`
+///
+/// The whole file is read into memory first, so `source` and `dest` may be
+/// the same path. `dest` is overwritten, not replaced atomically,
+/// see [`convert_file_in_place`] for that.
+/// Returns the line endings found in `source`.
+pub fn convert_file<P: AsRef<Path>, Q: AsRef<Path>>(
+    source: P,
+    dest: Q,
+    ending: LineEnding,
+) -> Result<CrlfStat, CrlfError> {
+    let (source, dest) = (source.as_ref(), dest.as_ref());
+    let input = std::fs::read(source).map_err(read_error(source))?;
+    let (output, stat) = convert_buffer(&input, ending).map_err(decode_error(source))?;
+    std::fs::write(dest, output).map_err(write_error(dest))?;
+    Ok(stat)
+}
+
+/// Convert the file at `path` to `ending`
+///
+/// The file is replaced atomically and keeps its permissions,
+/// see [`write_atomic`]. It isn't touched when nothing changes.
+pub fn convert_file_in_place<P: AsRef<Path>>(path: P, ending: LineEnding) -> Result<(), CrlfError> {
+    let path = path.as_ref();
+    let input = std::fs::read(path).map_err(read_error(path))?;
+    let (output, _) = convert_buffer(&input, ending).map_err(decode_error(path))?;
+    if output != input {
+        write_atomic(path, |dest| dest.write_all(&output)).map_err(write_error(path))?;
+    }
+    Ok(())
+}
+
+fn convert_buffer(input: &[u8], ending: LineEnding) -> std::io::Result<(Vec<u8>, CrlfStat)> {
+    let mut output = Vec::with_capacity(input.len());
+    let stat = match utf16::detect_bom(input) {
+        Some(order) => utf16::convert_to(input, &mut output, order, ending),
+        None => convert_to(input, &mut output, ending),
+    }?;
+    Ok((output, stat))
+}
+
+/// Replace the content of `path` by renaming a temporary file over it,
+/// so a failed write never leaves the original file truncated
+///
+/// The permissions of the original file are carried over to the new one.
+pub fn write_atomic<F, E>(path: &Path, write: F) -> Result<(), E>
+where
+    F: FnOnce(&mut BufWriter<&File>) -> Result<(), E>,
+    E: From<std::io::Error>,
+{
+    let permissions = std::fs::metadata(path)?.permissions();
+    let tmp_path = temp_path(path);
+    let tmp = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&tmp_path)?;
+    let result = write_temp(&tmp, permissions, write).and_then(|_| {
+        drop(tmp);
+        Ok(std::fs::rename(&tmp_path, path)?)
+    });
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+fn write_temp<F, E>(tmp: &File, permissions: std::fs::Permissions, write: F) -> Result<(), E>
+where
+    F: FnOnce(&mut BufWriter<&File>) -> Result<(), E>,
+    E: From<std::io::Error>,
+{
+    let mut writer = BufWriter::new(tmp);
+    write(&mut writer)?;
+    writer.flush()?;
+    drop(writer);
+    copy_permissions(tmp, permissions)?;
+    tmp.sync_all()?;
+    Ok(())
+}
+
+#[cfg(unix)]
+fn copy_permissions(file: &File, permissions: std::fs::Permissions) -> std::io::Result<()> {
+    file.set_permissions(permissions)
+}
+
+/// Only the read-only flag exists on other platforms,
+/// and a read-only file can't be replaced anyway
+#[cfg(not(unix))]
+fn copy_permissions(_file: &File, _permissions: std::fs::Permissions) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Temporary file next to `path`, the rename is only atomic within the same file system
+fn temp_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(format!(".{}.crlf-tmp", std::process::id()));
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_dir::TestDir;
+    use crate::FileKind;
+
+    #[test]
+    fn test_convert_file() {
+        let dir = TestDir::new("convert");
+        let dest = dir.join("converted");
+        let stat = convert_file("test/Cargo.toml.mixed", &dest, LineEnding::LF).unwrap();
+        assert_eq!(stat.crlf(), 8);
+        assert_eq!(
+            std::fs::read(&dest).unwrap(),
+            std::fs::read("test/Cargo.toml.lf").unwrap()
+        );
+
+        let err = convert_file(dir.join("missing"), &dest, LineEnding::LF).unwrap_err();
+        assert!(matches!(err, CrlfError::Read { .. }));
+        assert_eq!(err.path(), dir.join("missing"));
+        let err = convert_file(&dest, dir.join("missing/dest"), LineEnding::LF).unwrap_err();
+        assert!(matches!(err, CrlfError::Write { .. }));
+        std::fs::write(&dest, b"\xFF\xFEa").unwrap();
+        let err = convert_file(&dest, &dest, LineEnding::LF).unwrap_err();
+        assert!(matches!(err, CrlfError::Decode { .. }));
+    }
+
+    #[test]
+    fn test_in_place() {
+        let dir = TestDir::new("in-place");
+        let path = dir.join("file");
+        std::fs::copy("test/Cargo.toml.mixed", &path).unwrap();
+        assert_eq!(measure_path(&path).unwrap().classify(), FileKind::Mixed);
+        convert_file_in_place(&path, LineEnding::CRLF).unwrap();
+        assert_eq!(
+            std::fs::read(&path).unwrap(),
+            std::fs::read("test/Cargo.toml.crlf").unwrap()
+        );
+        assert_eq!(
+            measure_path(&path).unwrap().is_pure(),
+            Some(LineEnding::CRLF)
+        );
+        // only the converted file is left, no temporary one
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        let err = convert_file_in_place(dir.join("missing"), LineEnding::LF).unwrap_err();
+        assert!(matches!(err, CrlfError::Read { .. }));
+        std::fs::write(&path, b"\xFF\xFEa").unwrap();
+        assert!(matches!(measure_path(&path), Err(CrlfError::Decode { .. })));
+    }
+}
@@ -0,0 +1,152 @@
+//! Record of the files converted by a run, so `undo` can convert them back
+//!
+//! One JSON object per line, written as the files are converted:
+//!
+//! ```text
+//! {"path":"/repo/src/main.c","ending":"crlf","sha256":"9f86d0…"}
+//! {"path":"/repo/src/util.c","ending":"mixed","sha256":"60303a…","runs":[["crlf",12],["lf",3]]}
+//! ```
+//!
+//! `ending` is the kind the file had before, see [`crlf::FileKind`], and
+//! `sha256` the hash of the converted content, so a file changed since
+//! isn't touched by `undo`. A mixed file also has `runs`, the endings of its
+//! lines in order, each with the number of lines in a row using it.
+
+use anyhow::Context;
+use crlf::LineEnding;
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct Entry {
+    pub path: PathBuf,
+    pub ending: String,
+    pub sha256: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub runs: Vec<(String, usize)>,
+}
+
+impl Entry {
+    /// The ending of every terminated line before converting, from `runs`
+    pub fn line_endings(&self) -> anyhow::Result<Vec<LineEnding>> {
+        let mut endings = vec![];
+        for (ending, count) in &self.runs {
+            let ending: LineEnding = ending.parse().context(format!(
+                "Parse the runs of {} in the journal failed",
+                self.path.display()
+            ))?;
+            endings.extend(std::iter::repeat_n(ending, *count));
+        }
+        Ok(endings)
+    }
+}
+
+/// Group `endings` into runs of the same ending, as recorded in [`Entry::runs`]
+pub fn runs(endings: impl IntoIterator<Item = LineEnding>) -> Vec<(String, usize)> {
+    let mut runs: Vec<(LineEnding, usize)> = vec![];
+    for ending in endings {
+        match runs.last_mut() {
+            Some((last, count)) if *last == ending => *count += 1,
+            _ => runs.push((ending, 1)),
+        }
+    }
+    runs.into_iter()
+        .map(|(ending, count)| (ending.to_string(), count))
+        .collect()
+}
+
+pub struct Journal {
+    path: PathBuf,
+    writer: BufWriter<File>,
+}
+
+impl Journal {
+    /// Start a new journal at `path`, an existing one is only overwritten with `force`
+    pub fn create(path: &Path, force: bool) -> anyhow::Result<Self> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .create_new(!force)
+            .truncate(true)
+            .open(path)
+            .context(format!(
+                "Create journal {} failed, use --force to overwrite an existing one",
+                path.display()
+            ))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            writer: BufWriter::new(file),
+        })
+    }
+
+    /// Record that `f`, which was `ending` before, has just been converted,
+    /// `runs` are the endings of its lines when it was mixed
+    ///
+    /// Written out right away, so the journal is complete even if the run fails later.
+    pub fn record(
+        &mut self,
+        f: &Path,
+        ending: &str,
+        runs: Vec<(String, usize)>,
+    ) -> anyhow::Result<()> {
+        let entry = Entry {
+            path: std::fs::canonicalize(f)
+                .context(format!("Find the full path of {} failed", f.display()))?,
+            ending: ending.to_string(),
+            sha256: sha256_file(f)?,
+            runs,
+        };
+        let write = |writer: &mut BufWriter<File>| {
+            serde_json::to_writer(&mut *writer, &entry)?;
+            writer.write_all(b"\n")?;
+            writer.flush()
+        };
+        write(&mut self.writer).context(format!("Write journal {} failed", self.path.display()))
+    }
+}
+
+/// Entries of the journal at `path`, in the order the files were converted
+pub fn read(path: &Path) -> anyhow::Result<Vec<Entry>> {
+    let file = File::open(path).context(format!("Read journal {} failed", path.display()))?;
+    let mut entries = vec![];
+    for (idx, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.context(format!("Read journal {} failed", path.display()))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry = serde_json::from_str(&line).context(format!(
+            "Parse line {} of journal {} failed",
+            idx + 1,
+            path.display()
+        ))?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Lowercase hex SHA-256 of the content of `f`
+pub fn sha256_file(f: &Path) -> anyhow::Result<String> {
+    let mut source =
+        BufReader::new(File::open(f).context(format!("Read file {} failed", f.display()))?);
+    let mut hasher = Sha256::new();
+    loop {
+        let block = source
+            .fill_buf()
+            .context(format!("Read file {} failed", f.display()))?;
+        if block.is_empty() {
+            break;
+        }
+        hasher.update(block);
+        let len = block.len();
+        source.consume(len);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect())
+}
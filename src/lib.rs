@@ -1,103 +1,1597 @@
-use std::io::{BufRead, Write};
+//! Measure and convert the line endings of text
+//!
+//! The `crlf` command line tool is built on this library. To depend on the
+//! library alone, without the dependencies of the tool, turn off the default
+//! `cli` feature:
+//!
+//! ```toml
+//! [dependencies]
+//! crlf = { version = "0.1", default-features = false }
+//! ```
+//!
+//! Everything works on [`BufRead`] sources and [`Write`] destinations,
+//! [`convert_str`] and [`convert_bytes`] on memory:
+//!
+//! ```
+//! use crlf::{convert_str, CrlfStat, FileKind, LineEnding};
+//!
+//! let stat = CrlfStat::measure_file(b"a\r\nb\n".as_slice()).unwrap();
+//! assert_eq!(stat.classify(), FileKind::Mixed);
+//! assert_eq!(convert_str("a\r\nb\n", LineEnding::LF), "a\nb\n");
+//! ```
+//!
+//! The functions taking a path, like [`measure_path`], aren't available
+//! on `wasm32-unknown-unknown`, which has no file system.
+//!
+//! The `async` feature adds `measure_file_async` and `convert_to_async`,
+//! the same on the `AsyncBufRead` and `AsyncWrite` traits of tokio.
+
+use std::{
+    io::{BufRead, BufReader, ErrorKind, Read, Write},
+    ops::RangeInclusive,
+};
+
+#[cfg(feature = "async")]
+mod async_io;
+mod error;
+#[cfg(test)]
+mod test_dir;
+// a browser has no file system, std::fs only fails there
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+mod file;
+pub mod utf16;
+
+#[cfg(feature = "async")]
+pub use async_io::{convert_to_async, convert_with_async, measure_file_async};
+pub use error::CrlfError;
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub use file::{convert_file, convert_file_in_place, measure_path, write_atomic};
 
 const CR: u8 = b'\r';
 const LF: u8 = b'\n';
 
 const CRLF_BUF: [u8; 2] = [CR, LF];
 const LF_BUF: [u8; 1] = [LF];
+const CR_BUF: [u8; 1] = [CR];
+
+/// UTF-8 byte order mark
+const BOM: [u8; 3] = [0xEF, 0xBB, 0xBF];
+
+/// UTF-8 encoded U+2028 LINE SEPARATOR
+const LINE_SEPARATOR: [u8; 3] = [0xE2, 0x80, 0xA8];
+/// UTF-8 encoded U+2029 PARAGRAPH SEPARATOR
+const PARAGRAPH_SEPARATOR: [u8; 3] = [0xE2, 0x80, 0xA9];
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum LineEnding {
     CRLF,
     LF,
+    /// Lone `\r`, used by classic Mac OS
+    CR,
+}
+
+impl LineEnding {
+    /// Every kind of line ending
+    pub const ALL: [LineEnding; 3] = [LineEnding::CRLF, LineEnding::LF, LineEnding::CR];
+
+    pub fn as_bytes(&self) -> &'static [u8] {
+        match self {
+            LineEnding::CRLF => &CRLF_BUF,
+            LineEnding::LF => &LF_BUF,
+            LineEnding::CR => &CR_BUF,
+        }
+    }
+}
+
+impl std::str::FromStr for LineEnding {
+    type Err = ParseLineEndingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "crlf" => Ok(LineEnding::CRLF),
+            "lf" => Ok(LineEnding::LF),
+            "cr" => Ok(LineEnding::CR),
+            _ => Err(ParseLineEndingError(s.to_string())),
+        }
+    }
+}
+
+/// Error of parsing a [`LineEnding`] from a string other than `crlf`, `lf` or `cr`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseLineEndingError(String);
+
+impl std::fmt::Display for ParseLineEndingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unknown line ending {:?}, expect crlf, lf or cr", self.0)
+    }
 }
 
+impl std::error::Error for ParseLineEndingError {}
+
 impl std::fmt::Display for LineEnding {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             LineEnding::CRLF => write!(f, "crlf"),
             LineEnding::LF => write!(f, "lf"),
+            LineEnding::CR => write!(f, "cr"),
+        }
+    }
+}
+
+/// What the command line tool does with the files it's given
+///
+/// Parsed from and displayed as the names used on the command line, like `set-crlf`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Measure,
+    /// Convert every file to the line ending given along with the action
+    Set,
+    SetCrlf,
+    SetLf,
+    /// Convert mixed files to the line ending most used in each of them
+    Normalize,
+    /// Fail if any file doesn't use crlf only, nothing is modified
+    CheckCrlf,
+    /// Fail if any file doesn't use lf only, nothing is modified
+    CheckLf,
+    /// List the files using more than one kind of line ending
+    FindMixed,
+    /// Convert the files recorded in a journal back to the line ending they had
+    Undo,
+}
+
+impl Action {
+    /// Every action, in the order they're listed on the command line
+    pub const ALL: [Action; 9] = [
+        Action::Measure,
+        Action::Set,
+        Action::SetCrlf,
+        Action::SetLf,
+        Action::Normalize,
+        Action::CheckCrlf,
+        Action::CheckLf,
+        Action::FindMixed,
+        Action::Undo,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::Measure => "measure",
+            Action::Set => "set",
+            Action::SetCrlf => "set-crlf",
+            Action::SetLf => "set-lf",
+            Action::Normalize => "normalize",
+            Action::CheckCrlf => "check-crlf",
+            Action::CheckLf => "check-lf",
+            Action::FindMixed => "find-mixed",
+            Action::Undo => "undo",
+        }
+    }
+
+    /// Whether the action rewrites files
+    pub fn converts(&self) -> bool {
+        matches!(
+            self,
+            Action::Set | Action::SetCrlf | Action::SetLf | Action::Normalize
+        )
+    }
+
+    /// Line ending every file is converted to
+    ///
+    /// `None` for `normalize`, which picks one per file, for `set`, which is
+    /// given one, and for the actions not converting.
+    pub fn target(&self) -> Option<LineEnding> {
+        match self {
+            Action::SetCrlf => Some(LineEnding::CRLF),
+            Action::SetLf => Some(LineEnding::LF),
+            _ => None,
+        }
+    }
+
+    /// Line ending every file is checked for, `None` for the actions not checking
+    pub fn expected(&self) -> Option<LineEnding> {
+        match self {
+            Action::CheckCrlf => Some(LineEnding::CRLF),
+            Action::CheckLf => Some(LineEnding::LF),
+            _ => None,
+        }
+    }
+}
+
+impl std::str::FromStr for Action {
+    type Err = ParseActionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Action::ALL
+            .into_iter()
+            .find(|action| action.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| ParseActionError(s.to_string()))
+    }
+}
+
+impl std::fmt::Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Error of parsing an [`Action`] from a string that isn't the name of one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseActionError(String);
+
+impl std::fmt::Display for ParseActionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unknown action {:?}", self.0)
+    }
+}
+
+impl std::error::Error for ParseActionError {}
+
+/// Read one line into `buf`, terminator included.
+///
+/// A line is terminated by `\n`, `\r\n` or a lone `\r`.
+/// Returns the number of bytes appended to `buf`, 0 means EOF.
+fn read_line<R: BufRead>(source: &mut R, buf: &mut Vec<u8>) -> std::io::Result<usize> {
+    let start = buf.len();
+    let mut after_cr = false;
+    loop {
+        let available = match source.fill_buf() {
+            Ok(available) => available,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        if after_cr {
+            // a CR followed by a LF is a single CRLF ending
+            if available.first() == Some(&LF) {
+                buf.push(LF);
+                source.consume(1);
+            }
+            break;
+        }
+        if available.is_empty() {
+            break;
+        }
+        match available.iter().position(|c| *c == LF || *c == CR) {
+            Some(idx) => {
+                let terminator = available[idx];
+                buf.extend_from_slice(&available[..=idx]);
+                source.consume(idx + 1);
+                if terminator == LF {
+                    break;
+                }
+                after_cr = true;
+            }
+            None => {
+                let len = available.len();
+                buf.extend_from_slice(available);
+                source.consume(len);
+            }
+        }
+    }
+    Ok(buf.len() - start)
+}
+
+/// Split the terminator off a line read by [`read_line`]
+fn split_ending(line: &[u8]) -> (&[u8], Option<LineEnding>) {
+    if let Some(content) = line.strip_suffix(&CRLF_BUF) {
+        (content, Some(LineEnding::CRLF))
+    } else if let Some(content) = line.strip_suffix(&LF_BUF) {
+        (content, Some(LineEnding::LF))
+    } else if let Some(content) = line.strip_suffix(&CR_BUF) {
+        (content, Some(LineEnding::CR))
+    } else {
+        (line, None)
+    }
+}
+
+/// Position of `ending` in [`LineEnding::ALL`]
+fn ending_index(ending: LineEnding) -> usize {
+    match ending {
+        LineEnding::CRLF => 0,
+        LineEnding::LF => 1,
+        LineEnding::CR => 2,
+    }
+}
+
+/// Overall line ending classification of a file, see [`CrlfStat::classify`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    /// Every line uses the same ending
+    Pure(LineEnding),
+    /// More than one kind of line ending is used
+    Mixed,
+    /// Nothing at all, a zero byte file
+    Empty,
+    /// Text but no line ending, a single unterminated line
+    ///
+    /// A file holding nothing but a byte order mark is one too.
+    NoEndings,
+}
+
+impl std::fmt::Display for FileKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FileKind::Pure(ending) => write!(f, "{ending}"),
+            FileKind::Mixed => write!(f, "mixed"),
+            FileKind::Empty => write!(f, "empty"),
+            FileKind::NoEndings => write!(f, "no-endings"),
         }
     }
 }
 
 #[derive(Debug, Clone, Default)]
 pub struct CrlfStat {
-    lf: usize,
-    crlf: usize,
+    /// Number of lines terminated by each ending, see [`ending_index`]
+    endings: [usize; 3],
+    final_newline: bool,
+    bom: bool,
+    /// Number of lines, including an unterminated last line
+    lines: usize,
+    interior_cr: usize,
+    /// Lone CRs since the last LF or CRLF, interior once one of those follows
+    cr_run: usize,
+    /// Line number of the first line terminated by each ending, see [`ending_index`]
+    first_lines: [Option<usize>; 3],
+    unicode_separators: usize,
+    blank_lines: usize,
+    violations: Vec<Violation>,
+    /// Byte offsets of the line endings, see [`ending_index`]
+    offsets: [Vec<usize>; 3],
+}
+
+/// A line terminated by an unexpected line ending, see [`MeasureOptions::record_violations`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Violation {
+    /// 1-based line number
+    pub line: usize,
+    pub ending: LineEnding,
+}
+
+/// Initial capacity of the buffer holding one line, it grows for longer lines
+pub const DEFAULT_LINE_CAPACITY: usize = 256;
+
+/// Size of the blocks read by [`measure_reader_with`] and [`convert_reader`],
+/// same as a [`BufReader`] made by [`BufReader::new`]
+pub const DEFAULT_BLOCK_SIZE: usize = 8 * 1024;
+
+/// Options for [`CrlfStat::measure_with`]
+///
+/// By default only the line endings are counted.
+#[derive(Debug, Clone)]
+pub struct MeasureOptions {
+    violations: Option<(LineEnding, usize)>,
+    unicode_separators: bool,
+    blank_lines: bool,
+    offsets: bool,
+    line_capacity: usize,
+    block_size: usize,
+}
+
+impl Default for MeasureOptions {
+    fn default() -> Self {
+        Self {
+            violations: None,
+            unicode_separators: false,
+            blank_lines: false,
+            offsets: false,
+            line_capacity: DEFAULT_LINE_CAPACITY,
+            block_size: DEFAULT_BLOCK_SIZE,
+        }
+    }
+}
+
+impl MeasureOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Initial capacity of the line buffer, [`DEFAULT_LINE_CAPACITY`] by default
+    ///
+    /// A small one saves memory when measuring many tiny files,
+    /// a large one saves reallocations on files with long lines.
+    pub fn line_capacity(mut self, capacity: usize) -> Self {
+        self.line_capacity = capacity;
+        self
+    }
+
+    /// Size of the blocks read by [`measure_reader_with`], [`DEFAULT_BLOCK_SIZE`] by default
+    pub fn block_size(mut self, size: usize) -> Self {
+        self.block_size = size;
+        self
+    }
+
+    /// Remember the first `limit` lines not terminated by `expected`
+    pub fn record_violations(mut self, expected: LineEnding, limit: usize) -> Self {
+        self.violations = Some((expected, limit));
+        self
+    }
+
+    /// Also count the UTF-8 encoded U+2028 and U+2029 separators,
+    /// see [`CrlfStat::unicode_separators`]
+    pub fn unicode_separators(mut self, enable: bool) -> Self {
+        self.unicode_separators = enable;
+        self
+    }
+
+    /// Also count the lines with nothing before their terminator,
+    /// see [`CrlfStat::blank_lines`]
+    pub fn blank_lines(mut self, enable: bool) -> Self {
+        self.blank_lines = enable;
+        self
+    }
+
+    /// Also remember the byte offset of every line ending, see [`CrlfStat::offsets`]
+    ///
+    /// That's one `usize` per line, measuring stays allocation free without it.
+    pub fn record_offsets(mut self, enable: bool) -> Self {
+        self.offsets = enable;
+        self
+    }
 }
 
 impl CrlfStat {
+    /// Returns the line ending if only one kind is used.
+    ///
+    /// `None` is returned for both mixed and empty files,
+    /// use [`CrlfStat::classify`] to tell them apart.
     pub fn is_pure(&self) -> Option<LineEnding> {
-        if self.lf == 0 && self.crlf != 0 {
-            return Some(LineEnding::CRLF);
+        let mut used = self.counts().filter(|(_, count)| *count != 0);
+        match (used.next(), used.next()) {
+            (Some((ending, _)), None) => Some(ending),
+            _ => None,
+        }
+    }
+
+    /// Overall line ending of the file
+    ///
+    /// A zero byte file is [`FileKind::Empty`] and a single unterminated line
+    /// [`FileKind::NoEndings`], neither of them is pure or mixed.
+    pub fn classify(&self) -> FileKind {
+        if self.lines == 0 {
+            return FileKind::Empty;
+        }
+        if self.endings == [0; 3] {
+            return FileKind::NoEndings;
         }
-        if self.lf != 0 && self.crlf == 0 {
-            return Some(LineEnding::LF);
+        match self.is_pure() {
+            Some(ending) => FileKind::Pure(ending),
+            None => FileKind::Mixed,
         }
-        None
+    }
+
+    /// The line ending of at least `percent` percent of the lines
+    ///
+    /// Pure files give their line ending for any `percent`, so this tells the
+    /// mixed files with a few stray endings from the really mixed ones.
+    /// Empty files give `None`.
+    pub fn mostly(&self, percent: f64) -> Option<LineEnding> {
+        let total: usize = self.endings.iter().sum();
+        if total == 0 {
+            return None;
+        }
+        let dominant = self.dominant();
+        (self.count(dominant) as f64 * 100.0 >= percent * total as f64).then_some(dominant)
+    }
+
+    /// The most used line ending.
+    ///
+    /// Ties are resolved in the order of lf, crlf and cr, so a file without
+    /// any line ending gives [`LineEnding::LF`].
+    pub fn dominant(&self) -> LineEnding {
+        self.dominant_or(LineEnding::LF)
+    }
+
+    /// The most used line ending, `prefer` wins the ties it takes part in.
+    ///
+    /// Other ties are resolved in the order of lf, crlf and cr.
+    pub fn dominant_or(&self, prefer: LineEnding) -> LineEnding {
+        let max = self.endings.into_iter().max().unwrap_or_default();
+        if self.count(prefer) == max {
+            return prefer;
+        }
+        [LineEnding::LF, LineEnding::CRLF, LineEnding::CR]
+            .into_iter()
+            .find(|ending| self.count(*ending) == max)
+            .unwrap_or(prefer)
+    }
+
+    /// Whether converting to `ending` would change anything
+    pub fn needs_conversion(&self, ending: LineEnding) -> bool {
+        !matches!(self.classify(), FileKind::Empty | FileKind::NoEndings)
+            && self.is_pure() != Some(ending)
+    }
+
+    /// Number of lines terminated by every kind of line ending, unused ones included
+    pub fn counts(&self) -> impl Iterator<Item = (LineEnding, usize)> + '_ {
+        LineEnding::ALL
+            .into_iter()
+            .map(|ending| (ending, self.count(ending)))
+    }
+
+    /// Number of lines terminated by `ending`
+    pub fn count(&self, ending: LineEnding) -> usize {
+        self.endings[ending_index(ending)]
     }
 
     pub fn lf(&self) -> usize {
-        self.lf
+        self.count(LineEnding::LF)
     }
 
     pub fn crlf(&self) -> usize {
-        self.crlf
+        self.count(LineEnding::CRLF)
+    }
+
+    pub fn cr(&self) -> usize {
+        self.count(LineEnding::CR)
+    }
+
+    /// Whether the file starts with a UTF-8 byte order mark
+    pub fn has_bom(&self) -> bool {
+        self.bom
+    }
+
+    /// Whether the last line is terminated by a line ending, always false for an empty file
+    pub fn ends_with_newline(&self) -> bool {
+        self.final_newline
+    }
+
+    /// Lone CRs inside a line terminated by LF or CRLF, like `a\rb\r\n`
+    ///
+    /// They're also counted by [`CrlfStat::cr`]. In a file meant to use LF or
+    /// CRLF they usually come from corruption or a double conversion,
+    /// see [`LoneCr`] for how conversion treats them.
+    pub fn interior_cr(&self) -> usize {
+        self.interior_cr
+    }
+
+    /// Number of U+2028 LINE SEPARATOR and U+2029 PARAGRAPH SEPARATOR in UTF-8
+    ///
+    /// Some tools treat them as line breaks while others don't. Only counted
+    /// when asked by [`MeasureOptions::unicode_separators`], 0 otherwise.
+    pub fn unicode_separators(&self) -> usize {
+        self.unicode_separators
+    }
+
+    /// Number of lines with nothing before their terminator, a BOM doesn't count
+    ///
+    /// Only counted when asked by [`MeasureOptions::blank_lines`], 0 otherwise.
+    pub fn blank_lines(&self) -> usize {
+        self.blank_lines
     }
 
-    pub fn measure_file<R: BufRead>(mut source: R) -> std::io::Result<CrlfStat> {
-        let mut buf = vec![];
+    /// 1-based number of the first line terminated by `ending`
+    pub fn first_line(&self, ending: LineEnding) -> Option<usize> {
+        self.first_lines[ending_index(ending)]
+    }
+
+    /// Byte offsets where the line endings of kind `ending` start, in order
+    ///
+    /// Only recorded when asked by [`MeasureOptions::record_offsets`], empty otherwise.
+    pub fn offsets(&self, ending: LineEnding) -> &[usize] {
+        &self.offsets[ending_index(ending)]
+    }
+
+    /// Byte offsets of the line endings other than the [`CrlfStat::dominant`] one, in order
+    ///
+    /// Where an editor would jump to fix a mixed file,
+    /// only recorded when asked by [`MeasureOptions::record_offsets`].
+    pub fn offending_offsets(&self) -> Vec<usize> {
+        let dominant = self.dominant();
+        let mut offsets: Vec<usize> = LineEnding::ALL
+            .into_iter()
+            .filter(|ending| *ending != dominant)
+            .flat_map(|ending| self.offsets(ending).iter().copied())
+            .collect();
+        offsets.sort_unstable();
+        offsets
+    }
+
+    /// Lines with an unexpected ending, only recorded when asked by [`MeasureOptions`]
+    pub fn violations(&self) -> &[Violation] {
+        &self.violations
+    }
+
+    /// Count a line terminated by `ending`, `None` is an unterminated last line
+    fn record(&mut self, ending: Option<LineEnding>, options: &MeasureOptions) {
+        self.lines += 1;
+        if let Some(ending) = ending {
+            self.add_ending(ending);
+        }
+        self.final_newline = ending.is_some();
+        if let (Some(ending), Some((expected, limit))) = (ending, options.violations) {
+            if ending != expected && self.violations.len() < limit {
+                self.violations.push(Violation {
+                    line: self.lines,
+                    ending,
+                });
+            }
+        }
+    }
+
+    /// Same as [`CrlfStat::record`] for a line ending starting at byte `offset`
+    fn record_at(&mut self, ending: Option<LineEnding>, offset: usize, options: &MeasureOptions) {
+        self.record(ending, options);
+        if let (Some(ending), true) = (ending, options.offsets) {
+            self.offsets[ending_index(ending)].push(offset);
+        }
+    }
+
+    /// Count one line ending, the lines are counted in order
+    fn add_ending(&mut self, ending: LineEnding) {
+        self.endings[ending_index(ending)] += 1;
+        let line = self.endings.iter().sum();
+        self.first_lines[ending_index(ending)].get_or_insert(line);
+        match ending {
+            LineEnding::CR => self.cr_run += 1,
+            _ => self.interior_cr += std::mem::take(&mut self.cr_run),
+        }
+    }
+
+    /// Count the line endings of `source`
+    ///
+    /// With the `memchr` feature this is [`CrlfStat::measure_fast`],
+    /// which gives the same results without copying every line.
+    pub fn measure_file<R: BufRead>(source: R) -> std::io::Result<CrlfStat> {
+        #[cfg(feature = "memchr")]
+        return Self::measure_fast(source);
+        #[cfg(not(feature = "memchr"))]
+        Self::measure_file_with_buf(source, &mut vec![])
+    }
+
+    /// Same as [`CrlfStat::measure_file`], reading lines into a caller owned buffer
+    ///
+    /// Reusing one buffer saves an allocation per file when measuring many files.
+    /// The buffer is cleared before use, its contents are unspecified after the call.
+    pub fn measure_file_with_buf<R: BufRead>(
+        source: R,
+        buf: &mut Vec<u8>,
+    ) -> std::io::Result<CrlfStat> {
+        Self::measure_buffered(source, buf, &MeasureOptions::default())
+    }
+
+    pub fn measure_with<R: BufRead>(
+        source: R,
+        options: &MeasureOptions,
+    ) -> std::io::Result<CrlfStat> {
+        Self::measure_buffered(
+            source,
+            &mut Vec::with_capacity(options.line_capacity),
+            options,
+        )
+    }
+
+    fn measure_buffered<R: BufRead>(
+        source: R,
+        buf: &mut Vec<u8>,
+        options: &MeasureOptions,
+    ) -> std::io::Result<CrlfStat> {
         let mut stat = CrlfStat::default();
+        let mut lines = LineEndings::with_buf(source, std::mem::take(buf));
+        let mut result = Ok(());
+        // byte offset of the current line
+        let mut offset = 0;
+        while let Some(line) = lines.next() {
+            match line {
+                Ok((_, ending)) => {
+                    let len = ending.map_or(0, |e| e.as_bytes().len());
+                    stat.record_at(ending, offset + lines.buf.len() - len, options);
+                    offset += lines.buf.len();
+                }
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+            if options.unicode_separators {
+                // a separator has no CR or LF in it, so it's never split between lines
+                stat.unicode_separators += split_unicode_separators(&lines.buf).count() - 1;
+            }
+            if options.blank_lines {
+                let (content, _) = split_ending(&lines.buf);
+                let content = match lines.line {
+                    1 => content.strip_prefix(&BOM).unwrap_or(content),
+                    _ => content,
+                };
+                stat.blank_lines += usize::from(content.is_empty());
+            }
+        }
+        stat.bom = lines.has_bom();
+        *buf = lines.buf;
+        result.map(|_| stat)
+    }
+
+    /// Same as [`CrlfStat::measure_file`], scanning whole blocks instead of copying every line
+    ///
+    /// The ending of a line is all that's needed to measure it, so this only
+    /// looks for CR and LF bytes in the buffer of `source`. A CR at the end of
+    /// one block and a LF at the start of the next still count as one CRLF.
+    pub fn measure_fast<R: BufRead>(mut source: R) -> std::io::Result<CrlfStat> {
+        let mut scan = FastScan::default();
         loop {
-            if source.read_until(LF, &mut buf)? == 0 {
+            let block = match source.fill_buf() {
+                Ok(block) => block,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            if block.is_empty() {
                 break;
             }
-            if buf.ends_with(&CRLF_BUF) {
-                stat.crlf += 1;
+            scan.block(block);
+            let len = block.len();
+            source.consume(len);
+        }
+        Ok(scan.finish())
+    }
+}
+
+/// State of [`CrlfStat::measure_fast`] between the blocks of a source
+#[derive(Debug, Default)]
+struct FastScan {
+    stat: CrlfStat,
+    head: Vec<u8>,
+    // absolute offset of the last CR, while it may still be followed by a LF
+    pending_cr: Option<usize>,
+    offset: usize,
+    last_byte: Option<u8>,
+}
+
+impl FastScan {
+    fn block(&mut self, block: &[u8]) {
+        let stat = &mut self.stat;
+        if self.head.len() < BOM.len() {
+            let len = block.len().min(BOM.len() - self.head.len());
+            self.head.extend_from_slice(&block[..len]);
+        }
+        let mut start = 0;
+        while let Some(idx) = find_ending(&block[start..]) {
+            let idx = start + idx;
+            let pos = self.offset + idx;
+            if block[idx] == LF {
+                if self.pending_cr == Some(pos.wrapping_sub(1)) {
+                    stat.add_ending(LineEnding::CRLF);
+                } else {
+                    if self.pending_cr.is_some() {
+                        stat.add_ending(LineEnding::CR);
+                    }
+                    stat.add_ending(LineEnding::LF);
+                }
+                self.pending_cr = None;
             } else {
-                stat.lf += 1;
+                if self.pending_cr.is_some() {
+                    stat.add_ending(LineEnding::CR);
+                }
+                self.pending_cr = Some(pos);
+            }
+            start = idx + 1;
+        }
+        if self
+            .pending_cr
+            .is_some_and(|pos| pos + 1 < self.offset + block.len())
+        {
+            stat.add_ending(LineEnding::CR);
+            self.pending_cr = None;
+        }
+        self.last_byte = block.last().copied();
+        self.offset += block.len();
+    }
+
+    fn finish(self) -> CrlfStat {
+        let mut stat = self.stat;
+        if self.pending_cr.is_some() {
+            stat.add_ending(LineEnding::CR);
+        }
+        stat.bom = self.head == BOM;
+        stat.final_newline = matches!(self.last_byte, Some(CR | LF));
+        stat.lines = stat.endings.iter().sum::<usize>()
+            + usize::from(self.last_byte.is_some() && !stat.final_newline);
+        stat
+    }
+}
+
+/// Index of the first CR or LF in `bytes`
+#[cfg(feature = "memchr")]
+fn find_ending(bytes: &[u8]) -> Option<usize> {
+    memchr::memchr2(LF, CR, bytes)
+}
+
+#[cfg(not(feature = "memchr"))]
+fn find_ending(bytes: &[u8]) -> Option<usize> {
+    bytes.iter().position(|c| *c == LF || *c == CR)
+}
+
+/// Iterator over the line ending of every line in a stream
+///
+/// Yields the 1-based line number and the ending of each line,
+/// `None` is an unterminated last line. Only one line is kept in memory.
+///
+/// ```
+/// use crlf::{LineEnding, LineEndings};
+///
+/// let source = b"a\nb\r\nc\n".as_slice();
+/// let first_offending = LineEndings::new(source)
+///     .map(Result::unwrap)
+///     .find(|(_, ending)| matches!(ending, Some(e) if *e != LineEnding::LF))
+///     .map(|(line, _)| line);
+/// assert_eq!(first_offending, Some(2));
+/// ```
+#[derive(Debug)]
+pub struct LineEndings<R> {
+    source: R,
+    buf: Vec<u8>,
+    line: usize,
+    bom: bool,
+}
+
+impl<R: BufRead> LineEndings<R> {
+    pub fn new(source: R) -> Self {
+        Self::with_buf(source, vec![])
+    }
+
+    fn with_buf(source: R, mut buf: Vec<u8>) -> Self {
+        buf.clear();
+        Self {
+            source,
+            buf,
+            line: 0,
+            bom: false,
+        }
+    }
+
+    /// Whether the stream starts with a UTF-8 byte order mark, known once the first line is read
+    pub fn has_bom(&self) -> bool {
+        self.bom
+    }
+}
+
+impl<R: BufRead> Iterator for LineEndings<R> {
+    type Item = std::io::Result<(usize, Option<LineEnding>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.buf.clear();
+        match read_line(&mut self.source, &mut self.buf) {
+            Ok(0) => None,
+            Ok(_) => {
+                if self.line == 0 {
+                    // the BOM contains no CR or LF, so it's always whole in the first line
+                    self.bom = self.buf.starts_with(&BOM);
+                }
+                self.line += 1;
+                Some(Ok((self.line, split_ending(&self.buf).1)))
             }
-            buf.clear();
+            Err(e) => Some(Err(e)),
         }
-        Ok(stat)
     }
 }
 
+/// Split `content` on the UTF-8 encoded U+2028 and U+2029 separators
+fn split_unicode_separators(content: &[u8]) -> impl DoubleEndedIterator<Item = &[u8]> {
+    let mut starts = vec![0];
+    let mut ends = vec![];
+    let mut idx = 0;
+    while let Some(pos) = content[idx..]
+        .windows(3)
+        .position(|w| w == LINE_SEPARATOR || w == PARAGRAPH_SEPARATOR)
+    {
+        ends.push(idx + pos);
+        idx += pos + 3;
+        starts.push(idx);
+    }
+    ends.push(content.len());
+    starts
+        .into_iter()
+        .zip(ends)
+        .map(|(start, end)| &content[start..end])
+}
+
+fn trim_trailing_ws(content: &[u8]) -> &[u8] {
+    let len = content
+        .iter()
+        .rposition(|c| *c != b' ' && *c != b'\t')
+        .map_or(0, |idx| idx + 1);
+    &content[..len]
+}
+
+/// Leading indentation of `content` as (width in columns, length in units)
+///
+/// A tab advances to the next multiple of `tab_width`.
+fn indentation<T: Copy + PartialEq + From<u8>>(content: &[T], tab_width: usize) -> (usize, usize) {
+    let mut width = 0;
+    let mut len = 0;
+    for c in content {
+        if *c == T::from(b' ') {
+            width += 1;
+        } else if *c == T::from(b'\t') {
+            width += tab_width - width % tab_width;
+        } else {
+            break;
+        }
+        len += 1;
+    }
+    (width, len)
+}
+
+/// Options for [`convert_with`]
+///
+/// ```
+/// use crlf::{convert_with, ConvertOptions, LineEnding};
+///
+/// let options = ConvertOptions::new(LineEnding::LF).strip_trailing_ws(true);
+/// let mut dest = vec![];
+/// convert_with(b"a \r\nb".as_slice(), &mut dest, &options).unwrap();
+/// assert_eq!(dest, b"a\nb");
+/// ```
+#[derive(Debug, Clone)]
+pub struct ConvertOptions {
+    ending: LineEnding,
+    ensure_final_newline: bool,
+    strip_trailing_ws: bool,
+    bom: Bom,
+    lone_cr: LoneCr,
+    replace_unicode_separators: bool,
+    indent: Indent,
+    lines: Option<RangeInclusive<usize>>,
+    preserve_shebang_lf: bool,
+    line_capacity: usize,
+    block_size: usize,
+}
+
+/// What conversion does with the byte order mark at the start, see [`ConvertOptions::bom`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Bom {
+    /// Keep it if there's one, don't add one otherwise
+    #[default]
+    Preserve,
+    /// Remove it if there's one
+    Strip,
+    /// Start the output with one if the source doesn't, an empty source stays empty
+    Add,
+}
+
+/// How conversion treats a CR not followed by LF, see [`ConvertOptions::lone_cr`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LoneCr {
+    /// It's a line ending, converted like any other
+    #[default]
+    Ending,
+    /// It's part of the line and kept as is
+    ///
+    /// The line goes on after it, so it counts as one line for
+    /// [`ConvertOptions::lines`] and the selector of [`convert_with_selector`],
+    /// and its indentation and trailing whitespace are only at its start and end.
+    Preserve,
+    /// It's a stray and removed, the line goes on after it like with [`LoneCr::Preserve`]
+    Remove,
+}
+
+/// How conversion rewrites the leading indentation, see [`ConvertOptions::indent`]
+///
+/// The width of the indentation is kept, tabs reaching up to the next
+/// multiple of the tab width. Whitespace after the first other character
+/// is never touched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Indent {
+    #[default]
+    Keep,
+    /// Indent with spaces only, tabs are this wide
+    Spaces(usize),
+    /// Indent with tabs this wide, followed by the spaces left over
+    Tabs(usize),
+}
+
+impl Indent {
+    fn tab_width(self) -> Option<usize> {
+        match self {
+            Indent::Keep => None,
+            Indent::Spaces(width) | Indent::Tabs(width) => Some(width),
+        }
+    }
+
+    /// Indentation `width` columns wide
+    fn units(self, width: usize) -> impl Iterator<Item = u8> {
+        let tabs = match self {
+            Indent::Tabs(tab_width) => width / tab_width,
+            _ => 0,
+        };
+        let spaces = width - tabs * self.tab_width().unwrap_or(0);
+        std::iter::repeat_n(b'\t', tabs).chain(std::iter::repeat_n(b' ', spaces))
+    }
+}
+
+impl ConvertOptions {
+    /// Convert every line ending to `ending` and leave everything else untouched
+    pub fn new(ending: LineEnding) -> Self {
+        Self {
+            ending,
+            ensure_final_newline: false,
+            strip_trailing_ws: false,
+            bom: Bom::Preserve,
+            lone_cr: LoneCr::Ending,
+            replace_unicode_separators: false,
+            indent: Indent::Keep,
+            lines: None,
+            preserve_shebang_lf: false,
+            line_capacity: DEFAULT_LINE_CAPACITY,
+            block_size: DEFAULT_BLOCK_SIZE,
+        }
+    }
+
+    /// Line ending written for every terminated line
+    pub fn ending(mut self, ending: LineEnding) -> Self {
+        self.ending = ending;
+        self
+    }
+
+    /// Terminate the last line if it isn't, an empty input stays empty
+    pub fn ensure_final_newline(mut self, enable: bool) -> Self {
+        self.ensure_final_newline = enable;
+        self
+    }
+
+    /// Remove spaces and tabs at the end of every line
+    pub fn strip_trailing_ws(mut self, enable: bool) -> Self {
+        self.strip_trailing_ws = enable;
+        self
+    }
+
+    /// What to do with the byte order mark at the start, it's kept as it is by default
+    ///
+    /// Only a whole one is recognized, a source of one or two bytes of it
+    /// is copied as is by [`Bom::Strip`].
+    pub fn bom(mut self, policy: Bom) -> Self {
+        self.bom = policy;
+        self
+    }
+
+    /// Remove the byte order mark at the start, same as [`Bom::Strip`]
+    /// or [`Bom::Preserve`] for [`ConvertOptions::bom`]
+    pub fn strip_bom(self, enable: bool) -> Self {
+        self.bom(if enable { Bom::Strip } else { Bom::Preserve })
+    }
+
+    /// Treatment of a CR not followed by LF, a line ending by default
+    ///
+    /// A file that only ever meant to use LF or CRLF may still contain lone CRs,
+    /// see [`CrlfStat::interior_cr`]. They're not line endings in such a file.
+    pub fn lone_cr(mut self, policy: LoneCr) -> Self {
+        self.lone_cr = policy;
+        self
+    }
+
+    /// Replace the UTF-8 encoded U+2028 and U+2029 separators by the line ending
+    ///
+    /// They're left alone by default. The UTF-16 conversion ignores this option.
+    pub fn replace_unicode_separators(mut self, enable: bool) -> Self {
+        self.replace_unicode_separators = enable;
+        self
+    }
+
+    /// Rewrite the leading indentation of every line, it's kept by default
+    ///
+    /// # Panics
+    ///
+    /// If the tab width of `indent` is 0.
+    pub fn indent(mut self, indent: Indent) -> Self {
+        assert_ne!(indent.tab_width(), Some(0), "tab width must not be 0");
+        self.indent = indent;
+        self
+    }
+
+    /// Only convert the 1-based lines in `range`, every line is converted by default
+    ///
+    /// The other lines are copied verbatim, line ending included, and none of
+    /// the other options apply to them. The lines are split at their endings
+    /// before the range is applied, so a range always starts and ends at a line
+    /// boundary and a CRLF is never split: it belongs to the line it terminates.
+    /// A lone CR is only a line boundary with [`LoneCr::Ending`].
+    pub fn lines(mut self, range: RangeInclusive<usize>) -> Self {
+        self.lines = Some(range);
+        self
+    }
+
+    /// Always terminate a first line starting with `#!` by LF, off by default
+    ///
+    /// Kernels take a CR before the LF as part of the interpreter path, so a
+    /// script converted to CRLF no longer runs. This is a special case, the
+    /// first line is converted like any other unless asked for.
+    /// The UTF-16 conversion ignores this option.
+    pub fn preserve_shebang_lf(mut self, enable: bool) -> Self {
+        self.preserve_shebang_lf = enable;
+        self
+    }
+
+    /// Whether the 1-based line `line` is converted, see [`ConvertOptions::lines`]
+    fn converts_line(&self, line: usize) -> bool {
+        self.lines
+            .as_ref()
+            .is_none_or(|range| range.contains(&line))
+    }
+
+    /// Write the line content `content` to `dest`, with its indentation rewritten
+    fn write_content<W: Write>(&self, dest: &mut W, content: &[u8]) -> std::io::Result<()> {
+        let Some(tab_width) = self.indent.tab_width() else {
+            return dest.write_all(content);
+        };
+        let (width, len) = indentation(content, tab_width);
+        let indent: Vec<u8> = self.indent.units(width).collect();
+        dest.write_all(&indent)?;
+        dest.write_all(&content[len..])
+    }
+
+    /// Initial capacity of the line buffer, see [`MeasureOptions::line_capacity`]
+    pub fn line_capacity(mut self, capacity: usize) -> Self {
+        self.line_capacity = capacity;
+        self
+    }
+
+    /// Size of the blocks read by [`convert_reader`], [`DEFAULT_BLOCK_SIZE`] by default
+    pub fn block_size(mut self, size: usize) -> Self {
+        self.block_size = size;
+        self
+    }
+
+    /// Whether a line terminated by `ending` in the source really ends there
+    fn ends_line(&self, ending: LineEnding) -> bool {
+        ending != LineEnding::CR || self.lone_cr == LoneCr::Ending
+    }
+}
+
+/// Convert every line ending to `ending`
+///
+/// Returns the line endings found in `source`, same as [`CrlfStat::measure_file`] would.
+/// With nothing to convert the text is passed through unchanged: an empty
+/// `source` leaves `dest` empty and a single unterminated line is copied as is.
 pub fn convert_to<R: BufRead, W: Write>(
+    source: R,
+    dest: W,
+    ending: LineEnding,
+) -> std::io::Result<CrlfStat> {
+    convert_with(source, dest, &ConvertOptions::new(ending))
+}
+
+/// Convert `source` into `dest` as described by `options`
+///
+/// Returns the line endings found in `source`, same as [`CrlfStat::measure_file`] would.
+pub fn convert_with<R: BufRead, W: Write>(
+    source: R,
+    dest: W,
+    options: &ConvertOptions,
+) -> std::io::Result<CrlfStat> {
+    convert_with_selector(source, dest, options, |_, _| options.ending)
+}
+
+/// Same as [`convert_with`], asking `selector` for the ending of every line
+///
+/// `selector` gets the 1-based line number and the content of the line, without
+/// its ending or the byte order mark. Its ending is used wherever
+/// [`convert_with`] would use the one of `options`, except for a shebang line
+/// kept LF by [`ConvertOptions::preserve_shebang_lf`]. It isn't asked for the
+/// lines outside [`ConvertOptions::lines`].
+///
+/// ```
+/// use crlf::{convert_with_selector, ConvertOptions, LineEnding};
+///
+/// // CRLF inside fenced code blocks only
+/// let mut fenced = false;
+/// let mut dest = vec![];
+/// let options = ConvertOptions::new(LineEnding::LF);
+/// let source = b"a\r\n```\r\nb\n```\nc\r\n".as_slice();
+/// convert_with_selector(source, &mut dest, &options, |_, line| {
+///     let fence = line.starts_with(b"```");
+///     fenced ^= fence;
+///     if fenced && !fence {
+///         LineEnding::CRLF
+///     } else {
+///         LineEnding::LF
+///     }
+/// })
+/// .unwrap();
+/// assert_eq!(dest, b"a\n```\nb\r\n```\nc\n");
+/// ```
+pub fn convert_with_selector<R, W, F>(
     mut source: R,
     mut dest: W,
+    options: &ConvertOptions,
+    mut selector: F,
+) -> std::io::Result<CrlfStat>
+where
+    R: BufRead,
+    W: Write,
+    F: FnMut(usize, &[u8]) -> LineEnding,
+{
+    let mut buf = Vec::with_capacity(options.line_capacity);
+    let mut converter = LineConverter::default();
+    while read_line(&mut source, &mut buf)? != 0 {
+        converter.line(&buf, &mut dest, options, &mut selector)?;
+        buf.clear();
+    }
+    let stat = converter.finish(&mut dest, options)?;
+    dest.flush()?;
+
+    Ok(stat)
+}
+
+/// State of [`convert_with`] between the lines of a source
+#[derive(Debug)]
+struct LineConverter {
+    stat: CrlfStat,
+    last_terminated: bool,
+    first_line: bool,
+    /// Ending selected for the last converted line, `None` before the first
+    last_selected: Option<LineEnding>,
+    /// 1-based number of the current line, a lone CR that isn't an ending doesn't end it
+    line: usize,
+    /// Whether the last piece read ended with such a CR, so the line goes on
+    continued: bool,
+    /// Ending written at the end of the current line
     ending: LineEnding,
-) -> std::io::Result<()> {
-    let mut buf = vec![];
+}
 
-    loop {
-        if source.read_until(LF, &mut buf)? == 0 {
-            break;
+impl Default for LineConverter {
+    fn default() -> Self {
+        Self {
+            stat: CrlfStat::default(),
+            last_terminated: true,
+            first_line: true,
+            last_selected: None,
+            line: 0,
+            continued: false,
+            ending: LineEnding::LF,
         }
-        let has_line_ending = buf.last().is_some_and(|c| *c == LF);
-        if has_line_ending {
-            buf.pop();
-            if buf.last() == Some(&CR) {
-                buf.pop();
-            }
+    }
+}
+
+impl LineConverter {
+    /// Convert one line read by [`read_line`], ending it as `selector` says
+    ///
+    /// A line cut by a lone CR that isn't a line ending is only a piece of the
+    /// line, the next one goes on with it.
+    fn line<W, F>(
+        &mut self,
+        line: &[u8],
+        dest: &mut W,
+        options: &ConvertOptions,
+        selector: &mut F,
+    ) -> std::io::Result<()>
+    where
+        W: Write,
+        F: FnMut(usize, &[u8]) -> LineEnding,
+    {
+        let stat = &mut self.stat;
+        let (mut content, line_ending) = split_ending(line);
+        stat.record(line_ending, &MeasureOptions::default());
+        let continuation = self.continued;
+        if !continuation {
+            self.line += 1;
         }
-        dest.write_all(&buf)?;
-        buf.clear();
-        if has_line_ending {
-            match ending {
-                LineEnding::CRLF => {
-                    dest.write_all(&CRLF_BUF)?;
-                }
-                LineEnding::LF => {
-                    dest.write_all(&LF_BUF)?;
+        self.continued = line_ending.is_some_and(|e| !options.ends_line(e));
+        let converts = options.converts_line(self.line);
+        let mut shebang = false;
+        if self.first_line {
+            // split off so the indentation of the first line is found
+            if let Some(rest) = content.strip_prefix(&BOM) {
+                stat.bom = true;
+                content = rest;
+                if options.bom != Bom::Strip || !converts {
+                    dest.write_all(&BOM)?;
                 }
+            } else if options.bom == Bom::Add && converts {
+                dest.write_all(&BOM)?;
             }
+            shebang = options.preserve_shebang_lf && content.starts_with(b"#!");
+            self.first_line = false;
+        }
+        if !converts {
+            dest.write_all(content)?;
+            dest.write_all(line_ending.map_or(&[][..], |e| e.as_bytes()))?;
+            // a final line out of range isn't touched either
+            self.last_terminated = true;
+            return Ok(());
+        }
+        if !continuation {
+            let selected = selector(self.line, content);
+            self.last_selected = Some(selected);
+            self.ending = if shebang { LineEnding::LF } else { selected };
+        }
+        let selected = self.last_selected.unwrap_or(options.ending);
+        let ending = self.ending;
+        // the indentation is at the start of the line, not after a lone CR
+        let mut indented = !continuation;
+        let mut write_content = |dest: &mut W, content: &[u8]| {
+            let written = if indented {
+                options.write_content(dest, content)
+            } else {
+                dest.write_all(content)
+            };
+            indented = true;
+            written
+        };
+        // whether the last thing written is an ending that replaced a separator
+        let mut separated = false;
+        if options.replace_unicode_separators {
+            let mut segments = split_unicode_separators(content);
+            content = segments.next_back().unwrap_or_default();
+            for segment in segments {
+                separated = true;
+                let segment = if options.strip_trailing_ws {
+                    trim_trailing_ws(segment)
+                } else {
+                    segment
+                };
+                write_content(dest, segment)?;
+                dest.write_all(selected.as_bytes())?;
+            }
+        }
+        // the trailing whitespace is at the end of the line, not before a lone CR
+        if options.strip_trailing_ws && !self.continued {
+            content = trim_trailing_ws(content);
+        }
+        write_content(dest, content)?;
+        let separated = separated && content.is_empty();
+        self.last_terminated = line_ending.is_some_and(|e| options.ends_line(e));
+        if self.last_terminated {
+            dest.write_all(ending.as_bytes())?;
+        } else if line_ending == Some(LineEnding::CR) && options.lone_cr == LoneCr::Preserve {
+            dest.write_all(&CR_BUF)?;
+        } else {
+            self.last_terminated = separated && line_ending.is_none();
+        }
+        Ok(())
+    }
+
+    /// Write what's left after the last line, `dest` isn't flushed
+    fn finish<W: Write>(self, dest: &mut W, options: &ConvertOptions) -> std::io::Result<CrlfStat> {
+        if options.ensure_final_newline && !self.last_terminated {
+            let ending = self.last_selected.unwrap_or(options.ending);
+            dest.write_all(ending.as_bytes())?;
+        }
+        Ok(self.stat)
+    }
+}
+
+/// Convert the line endings of the 1-based lines in `lines` to `ending`
+///
+/// The other lines are copied verbatim, see [`ConvertOptions::lines`]
+/// for how the range is applied.
+/// Returns the line endings found in all of `source`, same as [`CrlfStat::measure_file`] would.
+pub fn convert_range<R: BufRead, W: Write>(
+    source: R,
+    dest: W,
+    ending: LineEnding,
+    lines: RangeInclusive<usize>,
+) -> std::io::Result<CrlfStat> {
+    convert_with(source, dest, &ConvertOptions::new(ending).lines(lines))
+}
+
+/// Whether `a` and `b` use the same line endings, like both only LF
+///
+/// Only the [`FileKind`] is compared, not the number of lines, so a converted
+/// copy can be checked against a file known to have the expected ending.
+/// Two mixed, empty or unterminated sources count as the same too.
+pub fn same_ending<A: BufRead, B: BufRead>(a: A, b: B) -> std::io::Result<bool> {
+    Ok(CrlfStat::measure_file(a)?.classify() == CrlfStat::measure_file(b)?.classify())
+}
+
+/// Same as [`CrlfStat::measure_file`] for a plain [`Read`] source
+///
+/// `source` is wrapped in a [`BufReader`]. Prefer [`CrlfStat::measure_file`]
+/// when `source` is already buffered, like a `&[u8]` or a [`BufReader`],
+/// to avoid copying through a second buffer.
+pub fn measure_reader<R: Read>(source: R) -> std::io::Result<CrlfStat> {
+    CrlfStat::measure_file(BufReader::new(source))
+}
+
+/// Same as [`CrlfStat::measure_with`] for a plain [`Read`] source
+///
+/// `source` is read in blocks of [`MeasureOptions::block_size`].
+pub fn measure_reader_with<R: Read>(
+    source: R,
+    options: &MeasureOptions,
+) -> std::io::Result<CrlfStat> {
+    CrlfStat::measure_with(
+        BufReader::with_capacity(options.block_size, source),
+        options,
+    )
+}
+
+/// Same as [`convert_with`] for a plain [`Read`] source
+///
+/// `source` is wrapped in a [`BufReader`] reading blocks of
+/// [`ConvertOptions::block_size`]. Prefer [`convert_with`]
+/// when `source` is already buffered.
+pub fn convert_reader<R: Read, W: Write>(
+    source: R,
+    dest: W,
+    options: &ConvertOptions,
+) -> std::io::Result<CrlfStat> {
+    convert_with(
+        BufReader::with_capacity(options.block_size, source),
+        dest,
+        options,
+    )
+}
+
+/// Number of leading bytes inspected by [`is_binary`], same as git
+pub const BINARY_SNIFF_LEN: usize = 8000;
+
+/// Guess whether a file is binary from its leading bytes.
+///
+/// Like git, a file is considered binary when a NUL byte appears
+/// within the first [`BINARY_SNIFF_LEN`] bytes.
+/// UTF-16 text is full of NUL bytes, so a sample starting with
+/// a UTF-16 byte order mark is never binary.
+pub fn is_binary(sample: &[u8]) -> bool {
+    if utf16::detect_bom(sample).is_some() {
+        return false;
+    }
+    let len = sample.len().min(BINARY_SNIFF_LEN);
+    sample[..len].contains(&0)
+}
+
+/// Share of control characters in a sample above which [`sniff`] takes it for binary, in percent
+const CONTROL_PERCENT: usize = 10;
+
+/// What the leading bytes of a file look like, see [`sniff`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Content {
+    /// Valid UTF-8, which plain ASCII is too
+    Utf8,
+    /// Starting with a UTF-16 byte order mark
+    Utf16,
+    /// Text in some 8-bit encoding, like Latin-1, that isn't valid UTF-8
+    Text8Bit,
+    Binary,
+}
+
+impl Content {
+    /// Every kind of content
+    pub const ALL: [Content; 4] = [
+        Content::Utf8,
+        Content::Utf16,
+        Content::Text8Bit,
+        Content::Binary,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Content::Utf8 => "utf-8",
+            Content::Utf16 => "utf-16",
+            Content::Text8Bit => "8bit-text",
+            Content::Binary => "binary",
         }
     }
-    dest.flush()?;
+}
+
+impl std::fmt::Display for Content {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Guess what kind of content a file has from its leading bytes
+///
+/// Looks at the same window as [`is_binary`], but also takes a sample for
+/// binary when more than a tenth of it are control characters other than
+/// whitespace, and tells UTF-8 from other 8-bit text. A multi-byte character
+/// cut off by the end of the window is still UTF-8.
+pub fn sniff(sample: &[u8]) -> Content {
+    if utf16::detect_bom(sample).is_some() {
+        return Content::Utf16;
+    }
+    let window = &sample[..sample.len().min(BINARY_SNIFF_LEN)];
+    if window.contains(&0) {
+        return Content::Binary;
+    }
+    let control = window
+        .iter()
+        .filter(|c| c.is_ascii_control() && !b"\t\n\x0B\x0C\r\x1B".contains(c))
+        .count();
+    if control * 100 > window.len() * CONTROL_PERCENT {
+        return Content::Binary;
+    }
+    match std::str::from_utf8(window) {
+        Ok(_) => Content::Utf8,
+        // only incomplete at the end, where the window may have cut a character
+        Err(e) if e.error_len().is_none() && window.len() < sample.len() => Content::Utf8,
+        Err(_) => Content::Text8Bit,
+    }
+}
+
+/// Guess the line ending of a file from its leading bytes.
+///
+/// Only the first [`BINARY_SNIFF_LEN`] bytes are looked at, the same window as
+/// [`is_binary`], so one read of a file's start serves both.
+/// Returns the most used ending, or `None` when there's no line ending at all
+/// or two kinds are used equally often.
+/// A CR at the very end of the window is ignored, since the LF that would make
+/// it a CRLF may have been cut off.
+pub fn detect_from_sample(bytes: &[u8]) -> Option<LineEnding> {
+    let sample = &bytes[..bytes.len().min(BINARY_SNIFF_LEN)];
+    let sample = sample.strip_suffix(&CR_BUF).unwrap_or(sample);
+    let stat = CrlfStat::measure_fast(sample).expect("in-memory measuring never fails");
+    let mut counts = stat.endings;
+    counts.sort_unstable();
+    if counts[2] == 0 || counts[2] == counts[1] {
+        return None;
+    }
+    Some(stat.dominant())
+}
+
+/// Convert the line endings of an in-memory buffer.
+///
+/// A missing line ending on the last line is left as is.
+pub fn convert_bytes(input: &[u8], ending: LineEnding) -> Vec<u8> {
+    let mut dest = Vec::with_capacity(input.len());
+    convert_to(std::io::Cursor::new(input), &mut dest, ending)
+        .expect("in-memory conversion never fails");
+    dest
+}
+
+/// Convert the line endings of an in-memory string.
+///
+/// A missing line ending on the last line is left as is.
+pub fn convert_str(input: &str, ending: LineEnding) -> String {
+    // only ASCII CR and LF bytes are touched, so the result is still valid UTF-8
+    String::from_utf8(convert_bytes(input.as_bytes(), ending))
+        .expect("conversion keeps UTF-8 intact")
+}
+
+/// Same as [`convert_str`], rewriting `s` instead of allocating a new string
+///
+/// Converting to LF or CR only shrinks the text, to CRLF the buffer grows
+/// once by the number of endings that get longer.
+pub fn normalize_string_in_place(s: &mut String, ending: LineEnding) {
+    let mut bytes = std::mem::take(s).into_bytes();
+    match ending {
+        LineEnding::CRLF => grow_to_crlf(&mut bytes),
+        LineEnding::LF | LineEnding::CR => shrink_to(&mut bytes, ending.as_bytes()[0]),
+    }
+    // only ASCII CR and LF bytes are touched, so the result is still valid UTF-8
+    *s = String::from_utf8(bytes).expect("conversion keeps UTF-8 intact");
+}
+
+/// Replace every line ending in `bytes` by the single byte `ending`
+fn shrink_to(bytes: &mut Vec<u8>, ending: u8) {
+    let mut read = 0;
+    let mut write = 0;
+    while let Some(idx) = find_ending(&bytes[read..]) {
+        let idx = read + idx;
+        let len = if bytes.get(idx..idx + 2) == Some(&CRLF_BUF[..]) {
+            2
+        } else {
+            1
+        };
+        bytes.copy_within(read..idx, write);
+        write += idx - read;
+        bytes[write] = ending;
+        write += 1;
+        read = idx + len;
+    }
+    bytes.copy_within(read.., write);
+    bytes.truncate(write + bytes.len() - read);
+}
 
-    Ok(())
+/// Replace every line ending in `bytes` by CRLF, moving the text back to front
+fn grow_to_crlf(bytes: &mut Vec<u8>) {
+    let mut scan = FastScan::default();
+    scan.block(bytes);
+    let stat = scan.finish();
+    let mut read = bytes.len();
+    let mut write = read + stat.lf() + stat.cr();
+    bytes.resize(write, 0);
+    while let Some(idx) = bytes[..read].iter().rposition(|c| *c == LF || *c == CR) {
+        let len = read - idx - 1;
+        write -= len;
+        bytes.copy_within(idx + 1..read, write);
+        // a CR found here is a lone one, the one before a LF goes with it
+        read = if bytes[idx] == LF && idx > 0 && bytes[idx - 1] == CR {
+            idx - 1
+        } else {
+            idx
+        };
+        write -= CRLF_BUF.len();
+        bytes[write..write + CRLF_BUF.len()].copy_from_slice(&CRLF_BUF);
+    }
+    debug_assert_eq!(read, write);
 }
 
 #[cfg(test)]
@@ -121,6 +1615,241 @@ mod test {
         assert_eq!(stat.is_pure(), None);
         assert_eq!(stat.crlf(), 8);
         assert_eq!(stat.lf(), 6);
+        assert_eq!(stat.cr(), 0);
+        assert_eq!(
+            stat.counts().collect::<Vec<_>>(),
+            [
+                (LineEnding::CRLF, 8),
+                (LineEnding::LF, 6),
+                (LineEnding::CR, 0)
+            ]
+        );
+
+        let cr_file = std::fs::File::open("test/Cargo.toml.cr").unwrap();
+        let stat = CrlfStat::measure_file(BufReader::new(cr_file)).unwrap();
+        assert_eq!(stat.is_pure(), Some(LineEnding::CR));
+        assert_eq!(stat.cr(), 14);
+
+        let stat = CrlfStat::measure_file(Cursor::new(b"a\rb\r\nc\nd\r")).unwrap();
+        assert_eq!(stat.is_pure(), None);
+        assert_eq!(stat.cr(), 2);
+        assert_eq!(stat.crlf(), 1);
+        assert_eq!(stat.lf(), 1);
+    }
+
+    #[test]
+    fn test_violations() {
+        let mixed_file = std::fs::File::open("test/Cargo.toml.mixed").unwrap();
+        let options = MeasureOptions::new().record_violations(LineEnding::LF, 100);
+        let stat = CrlfStat::measure_with(BufReader::new(mixed_file), &options).unwrap();
+        let lines: Vec<_> = stat.violations().iter().map(|v| v.line).collect();
+        assert_eq!(lines, [2, 3, 5, 6, 11, 12, 13, 14]);
+        assert_eq!(stat.first_line(LineEnding::CRLF), Some(2));
+        assert_eq!(stat.first_line(LineEnding::LF), Some(1));
+        assert_eq!(stat.first_line(LineEnding::CR), None);
+        assert!(stat
+            .violations()
+            .iter()
+            .all(|v| v.ending == LineEnding::CRLF));
+
+        let options = MeasureOptions::new().record_violations(LineEnding::CRLF, 2);
+        let stat = CrlfStat::measure_with(Cursor::new(b"a\nb\rc\r\nd\ne"), &options).unwrap();
+        assert_eq!(
+            stat.violations(),
+            [
+                Violation {
+                    line: 1,
+                    ending: LineEnding::LF
+                },
+                Violation {
+                    line: 2,
+                    ending: LineEnding::CR
+                },
+            ]
+        );
+        assert_eq!(stat.lf(), 2);
+
+        let stat = CrlfStat::measure_file(Cursor::new(b"a\nb\r\n")).unwrap();
+        assert!(stat.violations().is_empty());
+    }
+
+    #[test]
+    fn test_line_endings() {
+        let mut lines = LineEndings::new(Cursor::new(b"\xEF\xBB\xBFa\r\n\nb\rc"));
+        assert!(!lines.has_bom());
+        let endings: Vec<_> = (&mut lines).map(Result::unwrap).collect();
+        assert_eq!(
+            endings,
+            [
+                (1, Some(LineEnding::CRLF)),
+                (2, Some(LineEnding::LF)),
+                (3, Some(LineEnding::CR)),
+                (4, None),
+            ]
+        );
+        assert!(lines.has_bom());
+        assert_eq!(LineEndings::new(Cursor::new(b"")).count(), 0);
+    }
+
+    #[test]
+    fn test_measure_with_buf() {
+        let mut buf = b"leftover\r\n".to_vec();
+        let stat = CrlfStat::measure_file_with_buf(Cursor::new(b"a\nb\r\n"), &mut buf).unwrap();
+        assert_eq!(stat.lf(), 1);
+        assert_eq!(stat.crlf(), 1);
+        assert!(buf.capacity() > 0);
+
+        let stat =
+            CrlfStat::measure_file_with_buf(Cursor::new(b"\xEF\xBB\xBF\r"), &mut buf).unwrap();
+        assert_eq!(stat.is_pure(), Some(LineEnding::CR));
+        assert!(stat.has_bom());
+    }
+
+    #[test]
+    fn test_classify() {
+        let mixed_file = std::fs::File::open("test/Cargo.toml.mixed").unwrap();
+        let stat = CrlfStat::measure_file(BufReader::new(mixed_file)).unwrap();
+        assert_eq!(stat.classify(), FileKind::Mixed);
+        assert_eq!(stat.dominant(), LineEnding::CRLF);
+
+        let stat = CrlfStat::measure_file(Cursor::new(b"a\rb\r\n")).unwrap();
+        assert_eq!(stat.classify(), FileKind::Mixed);
+        assert_eq!(stat.dominant(), LineEnding::CRLF);
+
+        let stat = CrlfStat::measure_file(Cursor::new(b"a\rb\r")).unwrap();
+        assert_eq!(stat.classify(), FileKind::Pure(LineEnding::CR));
+        assert_eq!(stat.dominant(), LineEnding::CR);
+
+        let stat = CrlfStat::measure_file(Cursor::new(b"")).unwrap();
+        assert_eq!(stat.classify(), FileKind::Empty);
+        assert_eq!(stat.is_pure(), None);
+        assert_eq!(stat.dominant(), LineEnding::LF);
+        assert!(!stat.needs_conversion(LineEnding::CRLF));
+
+        for input in [b"a".as_slice(), b"\xEF\xBB\xBF"] {
+            for stat in [
+                CrlfStat::measure_file_with_buf(Cursor::new(input), &mut vec![]).unwrap(),
+                CrlfStat::measure_fast(Cursor::new(input)).unwrap(),
+            ] {
+                assert_eq!(stat.classify(), FileKind::NoEndings);
+                assert_eq!(stat.is_pure(), None);
+                assert!(!stat.ends_with_newline());
+                assert!(!stat.needs_conversion(LineEnding::CRLF));
+            }
+        }
+
+        // nothing to convert, passed through as is
+        let mut dest = vec![];
+        assert_eq!(
+            convert_to(b"".as_slice(), &mut dest, LineEnding::CRLF)
+                .unwrap()
+                .classify(),
+            FileKind::Empty
+        );
+        assert!(dest.is_empty());
+        assert_eq!(convert_bytes(b"a", LineEnding::CRLF), b"a");
+        assert_eq!(
+            convert_bytes(b"\xEF\xBB\xBFa", LineEnding::CR),
+            b"\xEF\xBB\xBFa"
+        );
+
+        assert_eq!(FileKind::Pure(LineEnding::CRLF).to_string(), "crlf");
+        assert_eq!(FileKind::Mixed.to_string(), "mixed");
+        assert_eq!(FileKind::NoEndings.to_string(), "no-endings");
+    }
+
+    #[test]
+    fn test_dominant_or() {
+        let stat = CrlfStat::measure_file(Cursor::new(b"a\nb\r\n")).unwrap();
+        assert_eq!(stat.dominant_or(LineEnding::LF), LineEnding::LF);
+        assert_eq!(stat.dominant_or(LineEnding::CRLF), LineEnding::CRLF);
+        // cr doesn't take part in the tie
+        assert_eq!(stat.dominant_or(LineEnding::CR), LineEnding::LF);
+
+        let stat = CrlfStat::measure_file(Cursor::new(b"a\nb\r\nc\r\n")).unwrap();
+        assert_eq!(stat.dominant_or(LineEnding::LF), LineEnding::CRLF);
+
+        let stat = CrlfStat::measure_file(Cursor::new(b"")).unwrap();
+        assert_eq!(stat.dominant_or(LineEnding::CRLF), LineEnding::CRLF);
+    }
+
+    #[test]
+    fn test_mostly() {
+        let mut input = b"a\r\n".repeat(99);
+        input.extend_from_slice(b"b\n");
+        let stat = CrlfStat::measure_file(Cursor::new(input)).unwrap();
+        assert_eq!(stat.classify(), FileKind::Mixed);
+        assert_eq!(stat.mostly(99.0), Some(LineEnding::CRLF));
+        assert_eq!(stat.mostly(99.5), None);
+
+        let stat = CrlfStat::measure_file(Cursor::new(b"a\nb\r\n")).unwrap();
+        assert_eq!(stat.mostly(50.0), Some(LineEnding::LF));
+        assert_eq!(stat.mostly(51.0), None);
+
+        let stat = CrlfStat::measure_file(Cursor::new(b"a\r")).unwrap();
+        assert_eq!(stat.mostly(100.0), Some(LineEnding::CR));
+        let stat = CrlfStat::measure_file(Cursor::new(b"")).unwrap();
+        assert_eq!(stat.mostly(0.0), None);
+    }
+
+    #[test]
+    fn test_parse_line_ending() {
+        assert_eq!("crlf".parse(), Ok(LineEnding::CRLF));
+        assert_eq!("LF".parse(), Ok(LineEnding::LF));
+        assert_eq!("cr".parse(), Ok(LineEnding::CR));
+        assert!("lfcr".parse::<LineEnding>().is_err());
+        for ending in LineEnding::ALL {
+            assert_eq!(ending.to_string().parse(), Ok(ending));
+        }
+    }
+
+    #[test]
+    fn test_parse_action() {
+        assert_eq!("set-crlf".parse(), Ok(Action::SetCrlf));
+        assert_eq!("Find-Mixed".parse(), Ok(Action::FindMixed));
+        assert!("set_lf".parse::<Action>().is_err());
+        for action in Action::ALL {
+            assert_eq!(action.to_string().parse(), Ok(action));
+            assert_eq!(
+                action.target().is_some(),
+                action.converts() && !matches!(action, Action::Normalize | Action::Set)
+            );
+        }
+        assert_eq!(Action::CheckLf.expected(), Some(LineEnding::LF));
+        assert_eq!(Action::SetLf.expected(), None);
+    }
+
+    #[test]
+    fn test_final_newline() {
+        let lf_file = std::fs::File::open("test/Cargo.toml.lf").unwrap();
+        let stat = CrlfStat::measure_file(BufReader::new(lf_file)).unwrap();
+        assert!(stat.ends_with_newline());
+
+        for (input, expected) in [
+            (b"a\nb".as_slice(), false),
+            (b"a\nb\r\n", true),
+            (b"a\r", true),
+            (b"a", false),
+            (b"", false),
+        ] {
+            let stat = CrlfStat::measure_file(Cursor::new(input)).unwrap();
+            assert_eq!(stat.ends_with_newline(), expected);
+        }
+    }
+
+    #[test]
+    fn test_needs_conversion() {
+        let stat = CrlfStat::measure_file(Cursor::new(b"a\nb\n")).unwrap();
+        assert!(!stat.needs_conversion(LineEnding::LF));
+        assert!(stat.needs_conversion(LineEnding::CRLF));
+
+        let stat = CrlfStat::measure_file(Cursor::new(b"a\nb\r\n")).unwrap();
+        assert!(stat.needs_conversion(LineEnding::LF));
+        assert!(stat.needs_conversion(LineEnding::CRLF));
+
+        let stat = CrlfStat::measure_file(Cursor::new(b"abc")).unwrap();
+        assert!(!stat.needs_conversion(LineEnding::LF));
+        assert!(!stat.needs_conversion(LineEnding::CR));
     }
 
     #[test]
@@ -184,4 +1913,704 @@ mod test {
         assert_eq!(dst_buf, crlf_file);
         dst_buf.clear();
     }
+
+    #[test]
+    fn test_convert_cr() {
+        let lf_file = std::fs::read("test/Cargo.toml.lf").unwrap();
+        let crlf_file = std::fs::read("test/Cargo.toml.crlf").unwrap();
+        let cr_file = std::fs::read("test/Cargo.toml.cr").unwrap();
+        let mut dst_buf = vec![];
+
+        convert_to(
+            Cursor::new(&lf_file),
+            Cursor::new(&mut dst_buf),
+            LineEnding::CR,
+        )
+        .unwrap();
+        assert_eq!(dst_buf, cr_file);
+        dst_buf.clear();
+
+        convert_to(
+            Cursor::new(&crlf_file),
+            Cursor::new(&mut dst_buf),
+            LineEnding::CR,
+        )
+        .unwrap();
+        assert_eq!(dst_buf, cr_file);
+        dst_buf.clear();
+
+        convert_to(
+            Cursor::new(&cr_file),
+            Cursor::new(&mut dst_buf),
+            LineEnding::LF,
+        )
+        .unwrap();
+        assert_eq!(dst_buf, lf_file);
+        dst_buf.clear();
+
+        convert_to(
+            Cursor::new(&cr_file),
+            Cursor::new(&mut dst_buf),
+            LineEnding::CRLF,
+        )
+        .unwrap();
+        assert_eq!(dst_buf, crlf_file);
+        dst_buf.clear();
+    }
+
+    #[test]
+    fn test_interior_cr() {
+        let input = b"a\rb\r\r\nc\rd";
+        let stat = CrlfStat::measure_file(Cursor::new(input)).unwrap();
+        assert_eq!(stat.cr(), 3);
+        assert_eq!(stat.crlf(), 1);
+        assert_eq!(stat.interior_cr(), 2);
+
+        let stat = CrlfStat::measure_file(
+            std::fs::File::open("test/Cargo.toml.cr")
+                .map(BufReader::new)
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(stat.interior_cr(), 0);
+
+        let convert = |policy| {
+            let mut dest = vec![];
+            let options = ConvertOptions::new(LineEnding::LF).lone_cr(policy);
+            convert_with(input.as_slice(), &mut dest, &options).unwrap();
+            dest
+        };
+        assert_eq!(convert(LoneCr::Ending), b"a\nb\n\nc\nd");
+        assert_eq!(convert(LoneCr::Preserve), b"a\rb\r\nc\rd");
+        assert_eq!(convert(LoneCr::Remove), b"ab\ncd");
+
+        let mut dest = vec![];
+        let options = ConvertOptions::new(LineEnding::LF)
+            .lone_cr(LoneCr::Remove)
+            .ensure_final_newline(true);
+        convert_with(b"a\r".as_slice(), &mut dest, &options).unwrap();
+        assert_eq!(dest, b"a\n");
+    }
+
+    #[test]
+    fn test_detect_from_sample() {
+        let mixed_file = std::fs::read("test/Cargo.toml.mixed").unwrap();
+        assert_eq!(detect_from_sample(&mixed_file), Some(LineEnding::CRLF));
+        assert_eq!(detect_from_sample(b"a\rb\r"), Some(LineEnding::CR));
+        // the last CR may be half of a CRLF
+        assert_eq!(detect_from_sample(b"a\r\nb\rc\r"), None);
+        assert_eq!(detect_from_sample(b"a\nb\r\n"), None);
+        assert_eq!(detect_from_sample(b"abc"), None);
+        assert_eq!(detect_from_sample(b""), None);
+
+        let mut long = b"a\r\n".repeat(BINARY_SNIFF_LEN / 3);
+        long.extend(b"\n".repeat(BINARY_SNIFF_LEN));
+        assert_eq!(detect_from_sample(&long), Some(LineEnding::CRLF));
+    }
+
+    #[test]
+    fn test_reader() {
+        let crlf_file = std::fs::File::open("test/Cargo.toml.crlf").unwrap();
+        let stat = measure_reader(crlf_file).unwrap();
+        assert_eq!(stat.is_pure(), Some(LineEnding::CRLF));
+
+        let mixed_file = std::fs::File::open("test/Cargo.toml.mixed").unwrap();
+        let mut dest = vec![];
+        let stat =
+            convert_reader(mixed_file, &mut dest, &ConvertOptions::new(LineEnding::LF)).unwrap();
+        assert_eq!(stat.crlf(), 8);
+        assert_eq!(dest, std::fs::read("test/Cargo.toml.lf").unwrap());
+
+        // the sizes only change how the work is done, never the result
+        for size in [1, 2, 3, 64] {
+            let mixed_file = std::fs::File::open("test/Cargo.toml.mixed").unwrap();
+            let options = MeasureOptions::new().line_capacity(0).block_size(size);
+            let stat = measure_reader_with(mixed_file, &options).unwrap();
+            assert_eq!((stat.crlf(), stat.lf()), (8, 6));
+
+            let mixed_file = std::fs::File::open("test/Cargo.toml.mixed").unwrap();
+            let options = ConvertOptions::new(LineEnding::LF)
+                .line_capacity(size)
+                .block_size(size);
+            let mut dest = vec![];
+            convert_reader(mixed_file, &mut dest, &options).unwrap();
+            assert_eq!(dest, std::fs::read("test/Cargo.toml.lf").unwrap());
+        }
+    }
+
+    #[test]
+    fn test_offsets() {
+        let input = b"a\r\nb\nc\r\n\rd\n".as_slice();
+        let stat = CrlfStat::measure_file(input).unwrap();
+        assert!(stat.offsets(LineEnding::LF).is_empty());
+        let options = MeasureOptions::new().record_offsets(true);
+        let stat = CrlfStat::measure_with(input, &options).unwrap();
+        assert_eq!(stat.offsets(LineEnding::CRLF), [1, 6]);
+        assert_eq!(stat.offsets(LineEnding::LF), [4, 10]);
+        assert_eq!(stat.offsets(LineEnding::CR), [8]);
+        // lf wins the tie with crlf
+        assert_eq!(stat.offending_offsets(), [1, 6, 8]);
+    }
+
+    #[test]
+    fn test_blank_lines() {
+        let input = b"\xEF\xBB\xBF\r\n\na\n \n\r\r\nb".as_slice();
+        assert_eq!(CrlfStat::measure_file(input).unwrap().blank_lines(), 0);
+        let options = MeasureOptions::new().blank_lines(true);
+        let stat = CrlfStat::measure_with(input, &options).unwrap();
+        assert_eq!(stat.blank_lines(), 4);
+        assert_eq!(stat.lines, 7);
+    }
+
+    #[test]
+    fn test_unicode_separators() {
+        let input = "a\u{2028}b \u{2029}c\r\n\u{2028}".as_bytes();
+        let stat = CrlfStat::measure_file(input).unwrap();
+        assert_eq!(stat.unicode_separators(), 0);
+        let options = MeasureOptions::new().unicode_separators(true);
+        let stat = CrlfStat::measure_with(input, &options).unwrap();
+        assert_eq!(stat.unicode_separators(), 3);
+        assert_eq!(stat.crlf(), 1);
+
+        let options = ConvertOptions::new(LineEnding::LF)
+            .replace_unicode_separators(true)
+            .strip_trailing_ws(true);
+        let mut dest = vec![];
+        convert_with(input, &mut dest, &options).unwrap();
+        assert_eq!(dest, b"a\nb\nc\n\n");
+
+        let mut dest = vec![];
+        convert_with(input, &mut dest, &options.ensure_final_newline(true)).unwrap();
+        assert_eq!(dest, b"a\nb\nc\n\n");
+
+        let mut dest = vec![];
+        convert_with(input, &mut dest, &ConvertOptions::new(LineEnding::LF)).unwrap();
+        assert_eq!(dest, "a\u{2028}b \u{2029}c\n\u{2028}".as_bytes());
+    }
+
+    #[test]
+    fn test_final_bare_cr() {
+        // CRLF lines, the last one cut right after its CR
+        let input = std::fs::read("test/final-cr.crlf").unwrap();
+        for stat in [
+            CrlfStat::measure_file(Cursor::new(&input)).unwrap(),
+            CrlfStat::measure_fast(Cursor::new(&input)).unwrap(),
+        ] {
+            assert_eq!(stat.crlf(), 2);
+            assert_eq!(stat.cr(), 1);
+            assert_eq!(stat.lf(), 0);
+            assert!(stat.ends_with_newline());
+            assert_eq!(stat.classify(), FileKind::Mixed);
+        }
+        assert_eq!(
+            convert_bytes(&input, LineEnding::CRLF),
+            b"[package]\r\nname = \"crlf\"\r\nversion = \"0.1.0\"\r\n"
+        );
+    }
+
+    #[test]
+    fn test_read_line() {
+        let input = b"a\nb\r\nc\rd\r\re";
+        let expected = [b"a\n".as_slice(), b"b\r\n", b"c\r", b"d\r", b"\r", b"e"];
+
+        // a 1 byte buffer makes every CR land on a buffer boundary
+        for capacity in [1, 2, 64] {
+            let mut source = BufReader::with_capacity(capacity, input.as_slice());
+            let mut lines = vec![];
+            let mut buf = vec![];
+            while read_line(&mut source, &mut buf).unwrap() != 0 {
+                lines.push(buf.clone());
+                buf.clear();
+            }
+            assert_eq!(lines, expected);
+        }
+    }
+
+    #[test]
+    fn test_measure_fast() {
+        let summary = |stat: &CrlfStat| {
+            (
+                stat.endings,
+                stat.final_newline,
+                stat.bom,
+                stat.lines,
+                stat.interior_cr,
+                stat.first_lines,
+            )
+        };
+        let mut inputs = vec![
+            b"a\nb\r\nc\rd\r\re".to_vec(),
+            b"\xEF\xBB\xBFa\r\r\n\n\r".to_vec(),
+            b"\r\n".to_vec(),
+            b"\xEF\xBB".to_vec(),
+            b"a\rb\r\r\nc\rd".to_vec(),
+            vec![],
+        ];
+        for name in ["lf", "crlf", "cr", "mixed"] {
+            inputs.push(std::fs::read(format!("test/Cargo.toml.{name}")).unwrap());
+        }
+        for input in inputs {
+            // measure_file is measure_fast itself with the memchr feature
+            let stat = CrlfStat::measure_file_with_buf(Cursor::new(&input), &mut vec![]).unwrap();
+            let expected = summary(&stat);
+            // a 1 byte buffer splits every CRLF between two blocks
+            for capacity in [1, 2, 3, 64, 8192] {
+                let source = BufReader::with_capacity(capacity, input.as_slice());
+                let stat = CrlfStat::measure_fast(source).unwrap();
+                assert_eq!(
+                    summary(&stat),
+                    expected,
+                    "{input:?} in {capacity} byte blocks"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_same_ending() {
+        let fixture = |name: &str| std::fs::read(format!("test/Cargo.toml.{name}")).unwrap();
+        let (lf, crlf) = (fixture("lf"), fixture("crlf"));
+        assert!(!same_ending(lf.as_slice(), crlf.as_slice()).unwrap());
+        assert!(same_ending(lf.as_slice(), lf.as_slice()).unwrap());
+        let converted = convert_bytes(&crlf, LineEnding::LF);
+        assert!(same_ending(converted.as_slice(), lf.as_slice()).unwrap());
+        assert!(same_ending(b"a\nb".as_slice(), lf.as_slice()).unwrap());
+        assert!(!same_ending(b"a".as_slice(), lf.as_slice()).unwrap());
+    }
+
+    #[test]
+    fn test_convert_str() {
+        assert_eq!(convert_str("a\r\nb\nc", LineEnding::LF), "a\nb\nc");
+        assert_eq!(
+            convert_str("a\r\nb\nc\n", LineEnding::CRLF),
+            "a\r\nb\r\nc\r\n"
+        );
+        assert_eq!(convert_str("ä\rö\r\n", LineEnding::LF), "ä\nö\n");
+        assert_eq!(convert_str("", LineEnding::CRLF), "");
+    }
+
+    #[test]
+    fn test_convert_bytes() {
+        let crlf_file = std::fs::read("test/Cargo.toml.crlf").unwrap();
+        let mixed_file = std::fs::read("test/Cargo.toml.mixed").unwrap();
+        assert_eq!(convert_bytes(&mixed_file, LineEnding::CRLF), crlf_file);
+
+        // no trailing newline
+        assert_eq!(
+            convert_bytes(b"a\nb\r\nc", LineEnding::CRLF),
+            b"a\r\nb\r\nc"
+        );
+        // single line without any terminator
+        assert_eq!(convert_bytes(b"abc", LineEnding::CRLF), b"abc");
+        assert_eq!(convert_bytes(b"", LineEnding::LF), b"");
+    }
+
+    /// Sink that remembers the largest single write
+    #[derive(Default)]
+    struct MaxWrite {
+        total: usize,
+        max: usize,
+    }
+
+    impl Write for MaxWrite {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.total += buf.len();
+            self.max = self.max.max(buf.len());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Source that repeats `line` `count` times without allocating the whole input
+    struct RepeatLine {
+        line: Vec<u8>,
+        count: usize,
+        pos: usize,
+    }
+
+    impl std::io::Read for RepeatLine {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.count == 0 {
+                return Ok(0);
+            }
+            let rest = &self.line[self.pos..];
+            let len = rest.len().min(buf.len());
+            buf[..len].copy_from_slice(&rest[..len]);
+            self.pos += len;
+            if self.pos == self.line.len() {
+                self.pos = 0;
+                self.count -= 1;
+            }
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn test_convert_streaming() {
+        // 16 MiB of 64 byte lines
+        const LINE_COUNT: usize = 256 * 1024;
+        let line = [b"a".repeat(62).as_slice(), &CRLF_BUF].concat();
+        let source = RepeatLine {
+            line: line.clone(),
+            count: LINE_COUNT,
+            pos: 0,
+        };
+        let mut dest = MaxWrite::default();
+
+        convert_to(BufReader::new(source), &mut dest, LineEnding::LF).unwrap();
+        assert_eq!(dest.total, LINE_COUNT * (line.len() - 1));
+        // output is written line by line, never as a whole
+        assert!(dest.max <= line.len());
+    }
+
+    #[test]
+    fn test_is_binary() {
+        let lf_file = std::fs::read("test/Cargo.toml.lf").unwrap();
+        assert!(!is_binary(&lf_file));
+        assert!(!is_binary(b""));
+        assert!(is_binary(b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR"));
+
+        let mut late_nul = vec![b'a'; BINARY_SNIFF_LEN];
+        late_nul.push(0);
+        assert!(!is_binary(&late_nul));
+        assert!(!is_binary(b"\xFF\xFEa\0\r\0\n\0"));
+    }
+
+    #[test]
+    fn test_sniff() {
+        let lf_file = std::fs::read("test/Cargo.toml.lf").unwrap();
+        assert_eq!(sniff(&lf_file), Content::Utf8);
+        assert_eq!(sniff(b""), Content::Utf8);
+        assert_eq!(sniff("\u{FEFF}caf\u{E9}\r\n".as_bytes()), Content::Utf8);
+        assert_eq!(sniff(b"caf\xE9\r\n"), Content::Text8Bit);
+        assert_eq!(sniff(b"\xFF\xFEa\0\r\0\n\0"), Content::Utf16);
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR"), Content::Binary);
+        // no NUL, but mostly control characters
+        assert_eq!(sniff(b"\x01\x02\x03abc\x04\x05"), Content::Binary);
+        assert_eq!(sniff(b"\x1B[31mred\x1B[0m\x0C\n"), Content::Utf8);
+
+        // a character cut by the window
+        let mut cut = vec![b'a'; BINARY_SNIFF_LEN - 1];
+        cut.extend_from_slice("\u{E9}".as_bytes());
+        assert_eq!(sniff(&cut), Content::Utf8);
+        assert_eq!(sniff(&cut[..BINARY_SNIFF_LEN]), Content::Text8Bit);
+    }
+
+    #[test]
+    fn test_ensure_final_newline() {
+        let options = ConvertOptions::new(LineEnding::CRLF).ensure_final_newline(true);
+        let convert = |input: &[u8]| {
+            let mut dest = vec![];
+            convert_with(Cursor::new(input), &mut dest, &options).unwrap();
+            dest
+        };
+
+        assert_eq!(convert(b"a\nb"), b"a\r\nb\r\n");
+        assert_eq!(convert(b"a\nb\n"), b"a\r\nb\r\n");
+        assert_eq!(convert(b"a"), b"a\r\n");
+        assert_eq!(convert(b"\n"), b"\r\n");
+        assert_eq!(convert(b""), b"");
+    }
+
+    #[test]
+    fn test_strip_trailing_ws() {
+        let options = ConvertOptions::new(LineEnding::LF).strip_trailing_ws(true);
+        let mut dest = vec![];
+        convert_with(
+            Cursor::new(b"a b \t\r\n \t \r\n\tc\n\nd  "),
+            &mut dest,
+            &options,
+        )
+        .unwrap();
+        assert_eq!(dest, b"a b\n\n\tc\n\nd");
+    }
+
+    #[test]
+    fn test_indent() {
+        let convert = |input: &[u8], indent| {
+            let options = ConvertOptions::new(LineEnding::LF).indent(indent);
+            let mut dest = vec![];
+            convert_with(Cursor::new(input), &mut dest, &options).unwrap();
+            dest
+        };
+        let input = b"\xEF\xBB\xBF\ta\tb\r\n  \tc \n      d\n \n";
+        assert_eq!(
+            convert(input, Indent::Keep),
+            b"\xEF\xBB\xBF\ta\tb\n  \tc \n      d\n \n"
+        );
+        assert_eq!(
+            convert(input, Indent::Spaces(4)),
+            b"\xEF\xBB\xBF    a\tb\n    c \n      d\n \n"
+        );
+        assert_eq!(
+            convert(input, Indent::Tabs(4)),
+            b"\xEF\xBB\xBF\ta\tb\n\tc \n\t  d\n \n"
+        );
+        assert_eq!(convert(b"a  \tb", Indent::Tabs(2)), b"a  \tb");
+        let options = ConvertOptions::new(LineEnding::LF)
+            .replace_unicode_separators(true)
+            .indent(Indent::Tabs(2));
+        let mut dest = vec![];
+        convert_with("  a\u{2028}  b".as_bytes(), &mut dest, &options).unwrap();
+        assert_eq!(dest, b"\ta\n\tb");
+    }
+
+    #[test]
+    fn test_preserve_shebang_lf() {
+        let convert = |input: &[u8], preserve| {
+            let options = ConvertOptions::new(LineEnding::CRLF).preserve_shebang_lf(preserve);
+            let mut dest = vec![];
+            convert_with(Cursor::new(input), &mut dest, &options).unwrap();
+            dest
+        };
+        let script = std::fs::read("test/script.sh.crlf").unwrap();
+        assert_eq!(
+            convert(&script, true),
+            b"#!/bin/sh\nset -e\r\necho \"crlf\"\r\n"
+        );
+        assert_eq!(convert(&script, false), script);
+        assert_eq!(convert(b"#!/bin/sh\n#!\n", true), b"#!/bin/sh\n#!\r\n");
+        assert_eq!(convert(b"echo a\n", true), b"echo a\r\n");
+        assert_eq!(convert(b"#!/bin/sh", true), b"#!/bin/sh");
+    }
+
+    #[test]
+    fn test_convert_range() {
+        let convert = |input: &[u8], lines| {
+            let mut dest = vec![];
+            let stat = convert_range(Cursor::new(input), &mut dest, LineEnding::LF, lines).unwrap();
+            (dest, stat)
+        };
+        let (dest, stat) = convert(b"a\r\nb\r\nc\rd\r\ne", 2..=3);
+        assert_eq!(dest, b"a\r\nb\nc\nd\r\ne");
+        // the endings of the whole source
+        assert_eq!(stat.crlf(), 3);
+        assert_eq!(stat.cr(), 1);
+        assert_eq!(convert(b"a\r\nb\r\n", 3..=9).0, b"a\r\nb\r\n");
+        assert_eq!(convert(b"a\r\nb\r\n", 0..=1).0, b"a\nb\r\n");
+
+        // the options only apply within the range
+        let options = ConvertOptions::new(LineEnding::CRLF)
+            .strip_bom(true)
+            .strip_trailing_ws(true)
+            .ensure_final_newline(true)
+            .lines(2..=2);
+        let mut dest = vec![];
+        convert_with(b"\xEF\xBB\xBFa \nb \nc ".as_slice(), &mut dest, &options).unwrap();
+        assert_eq!(dest, b"\xEF\xBB\xBFa \nb\r\nc ");
+        let mut dest = vec![];
+        convert_with(b"a\nb".as_slice(), &mut dest, &options.lines(1..=2)).unwrap();
+        assert_eq!(dest, b"a\r\nb\r\n");
+    }
+
+    #[test]
+    fn test_bom() {
+        let stat = CrlfStat::measure_file(Cursor::new(b"\xEF\xBB\xBFa\r\nb\r\n")).unwrap();
+        assert!(stat.has_bom());
+        assert_eq!(stat.is_pure(), Some(LineEnding::CRLF));
+        let stat = CrlfStat::measure_file(Cursor::new(b"\xEF\xBB\xBF")).unwrap();
+        assert!(stat.has_bom());
+        let stat = CrlfStat::measure_file(Cursor::new(b"a\xEF\xBB\xBF\n")).unwrap();
+        assert!(!stat.has_bom());
+        let stat = CrlfStat::measure_file(Cursor::new(b"\xEF\xBB")).unwrap();
+        assert!(!stat.has_bom());
+
+        let convert = |input: &[u8], strip_bom: bool| {
+            let options = ConvertOptions::new(LineEnding::LF).strip_bom(strip_bom);
+            let mut dest = vec![];
+            convert_with(Cursor::new(input), &mut dest, &options).unwrap();
+            dest
+        };
+        assert_eq!(convert(b"\xEF\xBB\xBFa\r\n", false), b"\xEF\xBB\xBFa\n");
+        assert_eq!(convert(b"\xEF\xBB\xBFa\r\n", true), b"a\n");
+        assert_eq!(
+            convert(b"\xEF\xBB\xBF\r\n\xEF\xBB\xBF", true),
+            b"\n\xEF\xBB\xBF"
+        );
+        // too short to hold a BOM, nothing is removed
+        assert_eq!(convert(b"\xEF\xBB", true), b"\xEF\xBB");
+        assert_eq!(convert(b"\xEF", true), b"\xEF");
+    }
+
+    #[test]
+    fn test_bom_policy() {
+        let convert = |input: &[u8], policy| {
+            let options = ConvertOptions::new(LineEnding::LF).bom(policy);
+            let mut dest = vec![];
+            convert_with(Cursor::new(input), &mut dest, &options).unwrap();
+            dest
+        };
+        let bom_file = std::fs::read("test/bom.crlf").unwrap();
+        let lf = b"[package]\nname = \"crlf\"\n";
+        let bom_lf = [BOM.as_slice(), lf].concat();
+        assert_eq!(convert(&bom_file, Bom::Preserve), bom_lf);
+        assert_eq!(convert(&bom_file, Bom::Strip), lf);
+        assert_eq!(convert(&bom_file, Bom::Add), bom_lf);
+        assert_eq!(convert(&bom_file[BOM.len()..], Bom::Preserve), lf);
+        assert_eq!(convert(&bom_file[BOM.len()..], Bom::Strip), lf);
+        assert_eq!(convert(&bom_file[BOM.len()..], Bom::Add), bom_lf);
+
+        assert_eq!(convert(b"", Bom::Add), b"");
+        assert_eq!(convert(b"\xEF\xBB", Bom::Strip), b"\xEF\xBB");
+        assert_eq!(convert(b"\xEF\xBB", Bom::Add), b"\xEF\xBB\xBF\xEF\xBB");
+        let options = ConvertOptions::new(LineEnding::LF)
+            .bom(Bom::Add)
+            .lines(2..=2);
+        let mut dest = vec![];
+        convert_with(b"a\r\nb\r\n".as_slice(), &mut dest, &options).unwrap();
+        assert_eq!(dest, b"a\r\nb\n");
+    }
+
+    #[test]
+    fn test_convert_stat() {
+        let mixed_file = std::fs::read("test/Cargo.toml.mixed").unwrap();
+        let stat = convert_to(Cursor::new(&mixed_file), std::io::sink(), LineEnding::LF).unwrap();
+        assert_eq!(stat.crlf(), 8);
+        assert_eq!(stat.lf(), 6);
+        assert!(stat.ends_with_newline());
+
+        let options = ConvertOptions::new(LineEnding::CR)
+            .strip_bom(true)
+            .ensure_final_newline(true);
+        let stat =
+            convert_with(Cursor::new(b"\xEF\xBB\xBFa\rb"), std::io::sink(), &options).unwrap();
+        assert_eq!(stat.cr(), 1);
+        assert!(stat.has_bom());
+        assert!(!stat.ends_with_newline());
+    }
+
+    /// Convert `input` in memory, returning the output and the endings found in `input`
+    fn round_trip(input: &[u8], ending: LineEnding) -> (Vec<u8>, CrlfStat) {
+        let mut dest = vec![];
+        let stat = convert_to(Cursor::new(input), &mut dest, ending).unwrap();
+        (dest, stat)
+    }
+
+    #[test]
+    fn test_normalize_in_place() {
+        let inputs = [
+            "a\r\nb\nc\rd",
+            "\r\n\r\r\n\n\n\r",
+            "\u{FEFF}é\r\n…\r",
+            "no ending",
+            "",
+        ];
+        for input in inputs {
+            for ending in LineEnding::ALL {
+                let mut s = input.to_string();
+                normalize_string_in_place(&mut s, ending);
+                assert_eq!(s, convert_str(input, ending), "{input:?} to {ending}");
+            }
+        }
+
+        let mut s = String::with_capacity(64);
+        s.push_str("a\r\nb\r\n");
+        let ptr = s.as_ptr();
+        normalize_string_in_place(&mut s, LineEnding::LF);
+        assert_eq!(s, "a\nb\n");
+        assert_eq!(s.as_ptr(), ptr);
+        assert_eq!(s.capacity(), 64);
+    }
+
+    #[test]
+    fn test_convert_with_selector() {
+        let mut seen = vec![];
+        let mut dest = vec![];
+        let options = ConvertOptions::new(LineEnding::LF)
+            .ensure_final_newline(true)
+            .lines(2..=3);
+        let stat = convert_with_selector(
+            b"\xEF\xBB\xBFa\nb\nc\rd\n".as_slice(),
+            &mut dest,
+            &options,
+            |line, content| {
+                seen.push((line, content.to_vec()));
+                LineEnding::CRLF
+            },
+        )
+        .unwrap();
+        assert_eq!(stat.lines, 4);
+        assert_eq!(seen, [(2, b"b".to_vec()), (3, b"c".to_vec())]);
+        assert_eq!(dest, b"\xEF\xBB\xBFa\nb\r\nc\r\nd\n");
+
+        // the final newline added ends like the line before it
+        let mut dest = vec![];
+        let options = ConvertOptions::new(LineEnding::LF).ensure_final_newline(true);
+        convert_with_selector(b"a\nb".as_slice(), &mut dest, &options, |line, _| {
+            [LineEnding::LF, LineEnding::CR][line - 1]
+        })
+        .unwrap();
+        assert_eq!(dest, b"a\nb\r");
+    }
+
+    #[test]
+    fn test_lone_cr_in_line() {
+        let convert = |input: &[u8], options: &ConvertOptions| {
+            let mut dest = vec![];
+            let mut lines = vec![];
+            convert_with_selector(input, &mut dest, options, |line, content| {
+                lines.push((line, content.to_vec()));
+                LineEnding::CRLF
+            })
+            .unwrap();
+            (dest, lines)
+        };
+        let input = b"a \r b \nc\rd\ne\n".as_slice();
+        let preserve = ConvertOptions::new(LineEnding::CRLF).lone_cr(LoneCr::Preserve);
+
+        let (dest, lines) = convert(input, &preserve);
+        assert_eq!(dest, b"a \r b \r\nc\rd\r\ne\r\n");
+        assert_eq!(
+            lines,
+            [(1, b"a ".to_vec()), (2, b"c".to_vec()), (3, b"e".to_vec())]
+        );
+        // the lines an editor shows
+        let (dest, _) = convert(input, &preserve.clone().lines(2..=2));
+        assert_eq!(dest, b"a \r b \nc\rd\r\ne\n");
+        let (dest, _) = convert(
+            input,
+            &preserve.clone().lone_cr(LoneCr::Remove).lines(1..=2),
+        );
+        assert_eq!(dest, b"a  b \r\ncd\r\ne\n");
+
+        let options = preserve.strip_trailing_ws(true).indent(Indent::Tabs(2));
+        let (dest, _) = convert(b"  a \r  b \n", &options);
+        assert_eq!(dest, b"\ta \r  b\r\n");
+    }
+
+    proptest::proptest! {
+        // mostly line endings, so inputs are full of CRLF pairs, lone CRs and empty lines
+        #[test]
+        fn prop_round_trip(input in proptest::collection::vec(
+            proptest::sample::select(b"\r\n\r\nab \t\xEF".to_vec()),
+            0..64,
+        )) {
+            let (lf, stat) = round_trip(&input, LineEnding::LF);
+            let (crlf, lf_stat) = round_trip(&lf, LineEnding::CRLF);
+            let (lf_again, crlf_stat) = round_trip(&crlf, LineEnding::LF);
+            proptest::prop_assert_eq!(&lf_again, &lf);
+            proptest::prop_assert_eq!(round_trip(&lf, LineEnding::LF).0, lf);
+
+            // each conversion only left its own ending, and kept the lines and final newline
+            proptest::prop_assert_eq!(lf_stat.crlf() + lf_stat.cr(), 0);
+            proptest::prop_assert_eq!(crlf_stat.lf() + crlf_stat.cr(), 0);
+            for converted in [&lf_stat, &crlf_stat] {
+                proptest::prop_assert_eq!(converted.lines, stat.lines);
+                proptest::prop_assert_eq!(converted.ends_with_newline(), stat.ends_with_newline());
+            }
+            proptest::prop_assert_eq!(lf_stat.lf(), stat.lf() + stat.crlf() + stat.cr());
+        }
+
+        #[test]
+        fn prop_normalize_in_place(input in "[\r\na é]{0,64}") {
+            for ending in LineEnding::ALL {
+                let mut s = input.clone();
+                normalize_string_in_place(&mut s, ending);
+                proptest::prop_assert_eq!(s, convert_str(&input, ending));
+            }
+        }
+    }
 }
@@ -1,9 +1,33 @@
+mod attributes;
+mod config;
+mod diff;
+mod discovery;
+mod journal;
+#[cfg(test)]
+mod test_dir;
+
 use ansi_term::Color;
 use anyhow::{anyhow, Context};
+use attributes::Declared;
 use atty::Stream;
-use clap::Parser as _;
+use clap::{
+    builder::{PossibleValuesParser, TypedValueParser},
+    error::ErrorKind,
+    CommandFactory as _, FromArgMatches as _,
+};
 use crlf::*;
-use std::{fs::File, io::BufReader, path::PathBuf};
+use indicatif::{ProgressBar, ProgressStyle};
+use rayon::prelude::*;
+use std::{
+    cell::Cell,
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::{BufRead, BufReader, Cursor, Read, Write},
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::Instant,
+};
 
 #[derive(clap::Parser)]
 #[command(
@@ -12,143 +36,2359 @@ use std::{fs::File, io::BufReader, path::PathBuf};
     version = git_version::git_version!()
 )]
 struct Cli {
+    #[command(subcommand)]
+    command: Command,
+
+    /// The action of `command`
+    #[arg(skip = Action::Measure)]
     action: Action,
-    /// file name pattern (using glob)
-    ///
-    /// if --git-file(-g) is given, this pattern will be passed to git grep
-    #[arg(default_value = "**/*")]
-    pattern: String,
+
+    /// The patterns of `command`
+    #[arg(skip)]
+    patterns: Vec<String>,
+
+    /// Line ending of the set action
+    #[arg(skip)]
+    set_ending: Option<LineEnding>,
 
     /// Use git grep to get text file list
     #[arg(long, short)]
     git_file: bool,
 
+    /// Leave out the files git doesn't track yet, with --git-file(-g)
+    #[arg(long, requires = "git_file")]
+    tracked_only: bool,
+
+    /// Git to run, when it isn't the one on PATH
+    #[arg(long, value_name = "PATH", default_value = "git")]
+    git_bin: PathBuf,
+
+    /// Option put before every git command, like `--git-arg=-c --git-arg=core.quotepath=false`
+    ///
+    /// Only options of git itself are allowed. The ones changing the directory
+    /// git works in or paging its output are refused, like `-C` and `--paginate`
+    #[arg(long, value_name = "ARG", allow_hyphen_values = true)]
+    git_arg: Vec<String>,
+
+    /// Work from the top directory of the git repository instead of the current one
+    ///
+    /// The whole repository is processed wherever the tool is run from.
+    /// Patterns, --exclude and the printed paths are all relative to the top directory
+    #[arg(long)]
+    repo_root: bool,
+
+    /// Skip files nested more than N directories deep
+    ///
+    /// Depth counts from the directory a pattern starts from, the part before its
+    /// first wildcard, so files directly in it are at depth 0. Paths from git and
+    /// stdin count from the current directory.
+    #[arg(long, value_name = "N")]
+    max_depth: Option<usize>,
+
+    /// Match the patterns and --exclude without regard to case, so *.TXT matches file.txt
+    ///
+    /// Directories named without a wildcard are still looked up as written,
+    /// which depends on the file system
+    #[arg(long)]
+    ignore_case: bool,
+
+    /// Let the wildcards of the patterns match hidden files and directories too
+    ///
+    /// Names starting with a dot are only found when the pattern spells out the dot otherwise
+    #[arg(long)]
+    include_hidden: bool,
+
+    /// Add the SHA-256 of every file to the records of measure --format json
+    ///
+    /// Comparing them with an earlier run tells which files changed, off by
+    /// default as every file is read once more
+    #[arg(long)]
+    with_hash: bool,
+
+    /// Print every path relative to the current directory
+    ///
+    /// Otherwise paths are printed as found: absolute for an absolute pattern
+    /// or a followed symlink, relative for the others
+    #[arg(long, conflicts_with = "absolute")]
+    relative: bool,
+
+    /// Print every path as a full path
+    #[arg(long)]
+    absolute: bool,
+
+    /// Paths read from stdin are separated by NUL instead of newline,
+    /// so are the paths printed by find-mixed
+    #[arg(long, short = '0')]
+    null: bool,
+
+    /// Process the targets of symlinked files instead of skipping them
+    ///
+    /// Each target is processed once, even when several links point to it.
+    /// Only the files themselves are checked: glob patterns still descend into
+    /// symlinked directories, while git never lists files through them.
+    #[arg(long)]
+    follow_symlinks: bool,
+
+    /// Skip files matching this glob pattern, can be given multiple times
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+
+    /// Skip files ignored by .gitignore or .git/info/exclude
+    ///
+    /// The --git-file(-g) mode always does this
+    #[arg(long)]
+    respect_gitignore: bool,
+
+    /// Don't skip ignored files even if .crlf.toml sets `respect_gitignore`
+    #[arg(long, conflicts_with = "respect_gitignore")]
+    no_respect_gitignore: bool,
+
     /// Show detailed output
     #[arg(long, short)]
     verbose: bool,
+
+    /// Only print the totals when measuring
+    #[arg(long, conflicts_with = "quiet")]
+    summary_only: bool,
+
+    /// Order the files measure prints, they're printed as they're measured otherwise
+    ///
+    /// Nothing is printed before every file is measured then
+    #[arg(long, value_enum, conflicts_with = "summary_only")]
+    sort: Option<SortKey>,
+
+    /// Also print the totals of each file extension when measuring
+    #[arg(long)]
+    by_extension: bool,
+
+    /// When to color the output
+    #[arg(long, value_enum, default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
+
+    /// Only print mixed files when measuring and changed files when setting
+    #[arg(long, short, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Only report which files would be converted, don't write anything
+    ///
+    /// With --format json a versioned plan is printed, listing every file with
+    /// its current line endings, the target one and whether it would change
+    #[arg(long, short = 'n')]
+    dry_run: bool,
+
+    /// Same as --dry-run, and fail if converting would change any file
+    ///
+    /// Every option changing the content counts, not only the line endings
+    #[arg(long, conflicts_with = "diff")]
+    check_only: bool,
+
+    /// End a conversion with a line counting the changed files and lines,
+    /// like `changed 37 of 1200 files (112 crlf→lf, 3 cr→lf)`
+    #[arg(long, conflicts_with_all = ["dry_run", "check_only", "diff"])]
+    what_changed: bool,
+
+    /// Record every converted file with the line ending it had in FILE
+    ///
+    /// `crlf undo --journal FILE` converts them back. Only the line endings
+    /// are restored, not what other options like --strip-trailing-ws changed.
+    /// Mixed UTF-16 files aren't recorded, undo can't restore their lines.
+    /// An existing FILE is only overwritten with --force
+    #[arg(long, value_name = "FILE")]
+    journal: Option<PathBuf>,
+
+    /// Print a diff of what converting would change, with CR, LF and tab made
+    /// visible, instead of writing anything
+    #[arg(long, conflicts_with = "dry_run")]
+    diff: bool,
+
+    /// Copy each file to FILE<SUFFIX> before converting it
+    #[arg(long, value_name = "SUFFIX", num_args = 0..=1, require_equals = true, default_missing_value = ".bak")]
+    backup: Option<String>,
+
+    /// Convert files to the ending given by their `eol` attribute in .gitattributes
+    ///
+    /// Files with `-text` are left alone, the others are converted as usual
+    #[arg(long)]
+    use_gitattributes: bool,
+
+    /// Ignore .gitattributes even if .crlf.toml sets `use_gitattributes`
+    #[arg(long, conflicts_with = "use_gitattributes")]
+    no_use_gitattributes: bool,
+
+    /// Convert files to the ending given by `end_of_line` in .editorconfig
+    ///
+    /// Files without it are converted as usual, .gitattributes takes precedence
+    /// when --use-gitattributes is also given
+    #[arg(long)]
+    use_editorconfig: bool,
+
+    /// Ignore .editorconfig even if .crlf.toml sets `use_editorconfig`
+    #[arg(long, conflicts_with = "use_editorconfig")]
+    no_use_editorconfig: bool,
+
+    /// Keep the modification time of converted files
+    #[arg(long)]
+    preserve_mtime: bool,
+
+    /// Stop at the first file that fails instead of going on with the rest
+    #[arg(long)]
+    fail_fast: bool,
+
+    /// Overwrite existing backups
+    #[arg(long)]
+    force: bool,
+
+    /// Also process files that look binary
+    ///
+    /// By default files with a NUL byte near the start are skipped
+    #[arg(long)]
+    force_binary: bool,
+
+    /// Take a closer look at the start of the files to tell binary ones
+    ///
+    /// Files mostly made of control characters are binary too, not only the
+    /// ones with a NUL byte. verbose measure shows what each file was taken for
+    #[arg(long)]
+    sniff: bool,
+
+    /// Only process the files whose start looks like one of these, implies --sniff
+    #[arg(
+        long,
+        value_delimiter = ',',
+        value_parser = PossibleValuesParser::new(Content::ALL.map(|content| content.name()))
+            .map(|s| Content::ALL.into_iter().find(|content| content.name() == s).unwrap()),
+    )]
+    content: Vec<Content>,
+
+    /// Only convert the lines A to B, counting from 1 and including B
+    ///
+    /// The other lines are copied as they are. B may be left out to convert up to the end
+    #[arg(long, value_name = "A:B", value_parser = parse_lines)]
+    lines: Option<RangeInclusive<usize>>,
+
+    /// Mark mixed files with at least PERCENT percent of one line ending as mostly that
+    ///
+    /// measure shows them as l, c or r instead of X, so the files with a few
+    /// stray endings stand out from the really mixed ones
+    #[arg(long, value_name = "PERCENT", value_parser = parse_percent)]
+    threshold: Option<f64>,
+
+    /// Output format
+    #[arg(long, value_enum, default_value_t = Format::Text)]
+    format: Format,
+
+    /// Line ending used by normalize when a mixed file uses two endings equally often
+    ///
+    /// Defaults to the `ending` of .crlf.toml, or lf without one
+    #[arg(
+        long,
+        value_parser = ending_parser(),
+    )]
+    prefer: Option<LineEnding>,
+
+    /// Ignore the .crlf.toml found in the current directory or one of its parents
+    ///
+    /// The config gives default patterns, excludes and flags, the command line
+    /// adds to or overrides them
+    #[arg(long)]
+    no_config: bool,
+
+    /// Add a line ending to the last line if it has none
+    #[arg(long)]
+    ensure_final_newline: bool,
+
+    /// Remove spaces and tabs at the end of every line
+    #[arg(long)]
+    strip_trailing_ws: bool,
+
+    /// Remove the UTF-8 byte order mark at the start of files
+    #[arg(long)]
+    strip_bom: bool,
+
+    /// Add a UTF-8 byte order mark at the start of the files without one
+    #[arg(long, conflicts_with = "strip_bom")]
+    add_bom: bool,
+
+    /// Keep LF after a first line starting with `#!`, whatever the line ending set
+    ///
+    /// A CR there breaks running the script
+    #[arg(long)]
+    preserve_shebang_lf: bool,
+
+    /// Replace the tabs in the leading indentation by spaces, tabs being WIDTH wide
+    #[arg(
+        long,
+        value_name = "WIDTH",
+        value_parser = clap::value_parser!(u64).range(1..).map(|width| width as usize)
+    )]
+    tabs_to_spaces: Option<usize>,
+
+    /// Replace the spaces in the leading indentation by tabs WIDTH wide
+    #[arg(
+        long,
+        value_name = "WIDTH",
+        conflicts_with = "tabs_to_spaces",
+        value_parser = clap::value_parser!(u64).range(1..).map(|width| width as usize)
+    )]
+    spaces_to_tabs: Option<usize>,
+
+    /// How a CR not followed by LF is converted
+    ///
+    /// ending: it's a line ending like any other, preserve: it's kept as is,
+    /// remove: it's dropped
+    #[arg(
+        long,
+        default_value = "ending",
+        value_parser = PossibleValuesParser::new(["ending", "preserve", "remove"]).map(|s| {
+            match s.as_str() {
+                "preserve" => LoneCr::Preserve,
+                "remove" => LoneCr::Remove,
+                _ => LoneCr::Ending,
+            }
+        }),
+    )]
+    lone_cr: LoneCr,
+
+    /// Refuse to convert files with lone CRs between other line endings
+    ///
+    /// Such files are often corrupted, converting them may split lines that were
+    /// never meant to be. Doesn't apply when --lone-cr says how to treat them
+    #[arg(long)]
+    strict: bool,
+
+    /// Count the U+2028 and U+2029 separators when measuring, replace them by
+    /// the line ending when converting
+    ///
+    /// Only for UTF-8 files, it makes measuring slower
+    #[arg(long)]
+    unicode_separators: bool,
+
+    /// Maximum number of lines reported per file by the github and parseable formats
+    #[arg(long, default_value_t = 20)]
+    max_violations: usize,
+
+    /// Make the check actions tell with their exit code what the offending files use
+    ///
+    /// 0: every file uses the expected ending, 1: a file couldn't be checked,
+    /// 2: some file uses lf only, 3: crlf only, 4: cr only, 5: some file is mixed.
+    /// The highest code that applies wins
+    #[arg(long)]
+    detailed_exit_code: bool,
+
+    /// Make measure exit with 5 when some file is mixed, the same code as --detailed-exit-code
+    ///
+    /// 0: no file is mixed, 1: a file couldn't be measured, 5: some file is mixed.
+    /// Any failure to measure wins over mixed files
+    #[arg(long)]
+    fail_on_mixed: bool,
+
+    /// Size in bytes of the blocks files are read in
+    #[arg(
+        long,
+        default_value_t = DEFAULT_BLOCK_SIZE,
+        value_parser = clap::value_parser!(u64).range(1..).map(|size| size as usize)
+    )]
+    block_size: usize,
+
+    /// Number of files processed in parallel, 0 uses one job per CPU
+    #[arg(long, short, default_value_t = 1)]
+    jobs: usize,
+
+    /// Measure every file as soon as it's found instead of listing all of them first
+    ///
+    /// Output starts right away and the file list is never held in memory.
+    /// Only for measure with glob patterns, the files are measured one at a time
+    #[arg(long, conflicts_with_all = ["sort", "git_file", "jobs"])]
+    stream: bool,
+}
+
+fn parse_lines(value: &str) -> Result<RangeInclusive<usize>, String> {
+    let (start, end) = value
+        .split_once(':')
+        .ok_or_else(|| format!("{value} isn't a range like 10:20"))?;
+    let start: usize = start.parse().map_err(|e| format!("start {start:?}: {e}"))?;
+    let end: usize = match end {
+        "" => usize::MAX,
+        end => end.parse().map_err(|e| format!("end {end:?}: {e}"))?,
+    };
+    if start == 0 {
+        return Err("lines count from 1".to_string());
+    }
+    if start > end {
+        return Err(format!("{value} is empty"));
+    }
+    Ok(start..=end)
 }
 
-#[derive(Debug, PartialEq, Eq, clap::ValueEnum, Clone)]
-enum Action {
-    Measure,
-    SetCrlf,
-    SetLf,
+fn parse_percent(value: &str) -> Result<f64, String> {
+    let percent: f64 = value.parse().map_err(|e| format!("{e}"))?;
+    if (0.0..=100.0).contains(&percent) {
+        Ok(percent)
+    } else {
+        Err(format!("{percent} isn't between 0 and 100"))
+    }
+}
+
+/// The actions, every one but undo takes the patterns of the files to work on
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Count the line endings of every file
+    Measure(Targets),
+    /// Convert every file to the line ending given, lf, crlf or cr
+    Set {
+        /// Line ending to convert to, the `ending` of .crlf.toml when left out
+        #[arg(value_parser = ending_parser())]
+        ending: Option<LineEnding>,
+        #[command(flatten)]
+        targets: Targets,
+    },
+    // spelled `set crlf` and `set lf` now
+    #[command(hide = true)]
+    SetCrlf(Targets),
+    #[command(hide = true)]
+    SetLf(Targets),
+    /// Convert mixed files to the line ending most used in each of them
+    Normalize(Targets),
+    /// Fail if any file doesn't use crlf only, nothing is modified
+    CheckCrlf(Targets),
+    /// Fail if any file doesn't use lf only, nothing is modified
+    CheckLf(Targets),
+    /// List the files using more than one kind of line ending, fail if there's any
+    FindMixed(Targets),
+    /// Convert the files recorded by --journal back, no patterns needed
+    Undo,
+}
+
+#[derive(clap::Args)]
+struct Targets {
+    /// file name patterns (using glob)
+    ///
+    /// if --git-file(-g) is given, these patterns will be passed to git grep
+    ///
+    /// a directory stands for every file below it,
+    /// a single `-` reads the paths from stdin instead, one per line
+    ///
+    /// defaults to **/*, or when converting with stdin piped in,
+    /// to converting stdin to stdout
+    patterns: Vec<String>,
+}
+
+impl Command {
+    fn action(&self) -> Action {
+        match self {
+            Command::Measure(_) => Action::Measure,
+            Command::Set { .. } => Action::Set,
+            Command::SetCrlf(_) => Action::SetCrlf,
+            Command::SetLf(_) => Action::SetLf,
+            Command::Normalize(_) => Action::Normalize,
+            Command::CheckCrlf(_) => Action::CheckCrlf,
+            Command::CheckLf(_) => Action::CheckLf,
+            Command::FindMixed(_) => Action::FindMixed,
+            Command::Undo => Action::Undo,
+        }
+    }
+
+    fn patterns(&mut self) -> Vec<String> {
+        match self {
+            Command::Measure(targets)
+            | Command::Set { targets, .. }
+            | Command::SetCrlf(targets)
+            | Command::SetLf(targets)
+            | Command::Normalize(targets)
+            | Command::CheckCrlf(targets)
+            | Command::CheckLf(targets)
+            | Command::FindMixed(targets) => std::mem::take(&mut targets.patterns),
+            Command::Undo => vec![],
+        }
+    }
+}
+
+impl Cli {
+    /// The command line, every option is also taken after the action
+    fn command_line() -> clap::Command {
+        Cli::command().mut_args(|arg| {
+            if arg.is_positional() {
+                arg
+            } else {
+                arg.global(true)
+            }
+        })
+    }
+
+    fn try_parse_from<I, T>(args: I) -> Result<Cli, clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<std::ffi::OsString> + Clone,
+    {
+        let mut matches = Cli::command_line().try_get_matches_from(args)?;
+        let mut cli = Cli::from_arg_matches_mut(&mut matches)?;
+        cli.action = cli.command.action();
+        cli.patterns = cli.command.patterns();
+        // --check-only is a --dry-run with an exit code
+        cli.dry_run |= cli.check_only;
+        if let Command::Set { ending, .. } = cli.command {
+            cli.set_ending = ending;
+        }
+        Ok(cli)
+    }
+}
+
+/// Parser of a line ending value, listing the possible ones in the help
+fn ending_parser() -> impl TypedValueParser<Value = LineEnding> {
+    PossibleValuesParser::new(["lf", "crlf", "cr"]).map(|s| s.parse::<LineEnding>().unwrap())
+}
+
+#[derive(Debug, PartialEq, Eq, clap::ValueEnum, Clone, Copy)]
+enum ColorChoice {
+    /// Color when writing to a terminal and NO_COLOR isn't set
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Debug, PartialEq, Eq, clap::ValueEnum, Clone, Copy)]
+enum SortKey {
+    Path,
+    /// Most crlf lines first
+    Crlf,
+    /// Most lf lines first
+    Lf,
+    /// Mixed files first, the ones with the most lines off their dominant ending leading
+    Mixed,
+}
+
+impl SortKey {
+    fn sort<T>(self, stats: &mut [(PathBuf, CrlfStat, T)]) {
+        let not_mixed = |stat: &CrlfStat| stat.classify() != FileKind::Mixed;
+        let off_dominant = |stat: &CrlfStat| {
+            stat.counts().map(|(_, count)| count).sum::<usize>() - stat.count(stat.dominant())
+        };
+        stats.sort_by(|(a_path, a, _), (b_path, b, _)| {
+            match self {
+                SortKey::Path => std::cmp::Ordering::Equal,
+                SortKey::Crlf => b.crlf().cmp(&a.crlf()),
+                SortKey::Lf => b.lf().cmp(&a.lf()),
+                SortKey::Mixed => not_mixed(a)
+                    .cmp(&not_mixed(b))
+                    .then(off_dominant(b).cmp(&off_dominant(a))),
+            }
+            .then_with(|| a_path.cmp(b_path))
+        });
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, clap::ValueEnum, Clone, Copy)]
+enum Format {
+    Text,
+    Json,
+    /// GitHub Actions error annotations, for the check actions
+    Github,
+    /// `path:line: message` lines, for the check actions
+    Parseable,
+}
+
+/// Measure result of a single file in json output
+#[derive(serde::Serialize)]
+struct MeasureRecord {
+    path: String,
+    crlf: usize,
+    lf: usize,
+    cr: usize,
+    final_newline: bool,
+    bom: bool,
+    interior_cr: usize,
+    unicode_separators: usize,
+    kind: String,
+    /// Lowercase hex SHA-256 of the file, only with --with-hash
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sha256: Option<String>,
+}
+
+impl MeasureRecord {
+    fn new(path: &Path, stat: &CrlfStat, sha256: Option<String>) -> Self {
+        Self {
+            path: path.display().to_string(),
+            crlf: stat.crlf(),
+            lf: stat.lf(),
+            cr: stat.cr(),
+            final_newline: stat.ends_with_newline(),
+            bom: stat.has_bom(),
+            interior_cr: stat.interior_cr(),
+            unicode_separators: stat.unicode_separators(),
+            kind: stat.classify().to_string(),
+            sha256,
+        }
+    }
+}
+
+/// Version of the --dry-run --format json plan, raised when its fields change incompatibly
+const PLAN_VERSION: u32 = 1;
+
+/// What --dry-run --format json prints, the conversion set would do
+#[derive(serde::Serialize)]
+struct Plan {
+    version: u32,
+    files: Vec<PlanEntry>,
+}
+
+#[derive(serde::Serialize)]
+struct PlanEntry {
+    path: String,
+    /// Line endings used now, like in measure, `not-text` for the files left alone
+    kind: String,
+    /// `None` for the files left alone
+    target: Option<String>,
+    /// Whether converting would change the file
+    change: bool,
 }
 
 const CRLF_COLOR: ansi_term::Colour = Color::Yellow;
 const LF_COLOR: ansi_term::Colour = Color::Green;
+const CR_COLOR: ansi_term::Colour = Color::Cyan;
 const MIXED_COLOR: ansi_term::Colour = Color::Red;
+const EMPTY_COLOR: ansi_term::Colour = Color::White;
 
-fn main() -> anyhow::Result<()> {
-    let args = Cli::parse();
-
-    let files: Vec<PathBuf> = if args.git_file {
-        let git_result = std::process::Command::new("git")
-            .args([
-                "grep",
-                "-I",
-                "--name-only",
-                "--untracked",
-                "-e",
-                ".",
-                "--",
-                if args.pattern == "**/*" {
-                    "*"
-                } else {
-                    args.pattern.as_str()
-                },
-            ])
-            .output()
-            .context("Run git command failed")?;
-        if !git_result.status.success() {
-            if let Some(code) = git_result.status.code() {
-                return Err(anyhow!("Git command failed with exit code: {code}"));
+/// What a conversion changed over all files, for --what-changed
+#[derive(Debug, Default)]
+struct ChangeTally {
+    files: usize,
+    changed_files: usize,
+    /// Lines converted from one ending to another
+    lines: Vec<((LineEnding, LineEnding), usize)>,
+}
+
+impl ChangeTally {
+    /// Count a file converted to `target`, `lines` as from [`converted_counts`]
+    fn add_converted(&mut self, lines: &[(LineEnding, usize)], target: LineEnding) {
+        self.changed_files += 1;
+        for &(ending, count) in lines {
+            match self.lines.iter_mut().find(|(d, _)| *d == (ending, target)) {
+                Some((_, total)) => *total += count,
+                None => self.lines.push(((ending, target), count)),
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for ChangeTally {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "changed {} of {} files", self.changed_files, self.files)?;
+        let mut directions = vec![];
+        for from in LineEnding::ALL {
+            for to in LineEnding::ALL {
+                if let Some((_, count)) = self.lines.iter().find(|(d, _)| *d == (from, to)) {
+                    directions.push(format!("{count} {from}→{to}"));
+                }
+            }
+        }
+        if !directions.is_empty() {
+            write!(f, " ({})", directions.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Totals over all measured files
+#[derive(Debug, Default, serde::Serialize)]
+struct MeasureSummary {
+    crlf_files: usize,
+    lf_files: usize,
+    cr_files: usize,
+    mixed_files: usize,
+    empty_files: usize,
+    no_endings_files: usize,
+    crlf: usize,
+    lf: usize,
+    cr: usize,
+}
+
+impl MeasureSummary {
+    fn add(&mut self, stat: &CrlfStat) {
+        match stat.classify() {
+            FileKind::Pure(LineEnding::CRLF) => self.crlf_files += 1,
+            FileKind::Pure(LineEnding::LF) => self.lf_files += 1,
+            FileKind::Pure(LineEnding::CR) => self.cr_files += 1,
+            FileKind::Mixed => self.mixed_files += 1,
+            FileKind::Empty => self.empty_files += 1,
+            FileKind::NoEndings => self.no_endings_files += 1,
+        }
+        self.crlf += stat.crlf();
+        self.lf += stat.lf();
+        self.cr += stat.cr();
+    }
+
+    fn files(&self) -> usize {
+        self.crlf_files
+            + self.lf_files
+            + self.cr_files
+            + self.mixed_files
+            + self.empty_files
+            + self.no_endings_files
+    }
+
+    fn lines(&self) -> usize {
+        self.crlf + self.lf + self.cr
+    }
+
+    fn totals(&self) -> Totals<'_> {
+        Totals {
+            files: self.files(),
+            lines: self.lines(),
+            summary: self,
+        }
+    }
+
+    fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(&self.totals())
+    }
+
+    fn print(&self, color: bool, verbose: bool) {
+        self.print_files("files", color, verbose);
+        let lines = self.lines();
+        println!(
+            "lines: {}, {}, {}, {}",
+            lines,
+            paint(color, CRLF_COLOR, count(verbose, "crlf", self.crlf, lines)),
+            paint(color, LF_COLOR, count(verbose, "lf", self.lf, lines)),
+            paint(color, CR_COLOR, count(verbose, "cr", self.cr, lines)),
+        );
+    }
+
+    /// Print the file counts on one line starting with `label`
+    fn print_files(&self, label: &str, color: bool, verbose: bool) {
+        let files = self.files();
+        println!(
+            "{label}: {}, {}, {}, {}, {}, {}, {}",
+            files,
+            paint(
+                color,
+                CRLF_COLOR,
+                count(verbose, "crlf", self.crlf_files, files)
+            ),
+            paint(color, LF_COLOR, count(verbose, "lf", self.lf_files, files)),
+            paint(color, CR_COLOR, count(verbose, "cr", self.cr_files, files)),
+            paint(
+                color,
+                MIXED_COLOR,
+                count(verbose, "mixed", self.mixed_files, files)
+            ),
+            paint(
+                color,
+                EMPTY_COLOR,
+                count(verbose, "empty", self.empty_files, files)
+            ),
+            paint(
+                color,
+                EMPTY_COLOR,
+                count(verbose, "no-endings", self.no_endings_files, files)
+            ),
+        );
+    }
+}
+
+/// JSON form of a [`MeasureSummary`]
+#[derive(serde::Serialize)]
+struct Totals<'a> {
+    files: usize,
+    lines: usize,
+    #[serde(flatten)]
+    summary: &'a MeasureSummary,
+}
+
+/// Summary key of the extension of `path`
+fn extension_key(path: &Path) -> String {
+    match path.extension() {
+        Some(ext) => format!(".{}", ext.to_string_lossy()),
+        None => "(none)".to_string(),
+    }
+}
+
+fn count(verbose: bool, name: &str, value: usize, total: usize) -> String {
+    if verbose {
+        format!("{name}: {value} ({:.1}%)", percent(value, total))
+    } else {
+        format!("{name}: {value}")
+    }
+}
+
+fn paint(color: bool, colour: Color, text: String) -> String {
+    if color {
+        colour.paint(text).to_string()
+    } else {
+        text
+    }
+}
+
+fn paint_ending(ending: LineEnding, color: bool) -> String {
+    if !color {
+        return ending.to_string();
+    }
+    match ending {
+        LineEnding::CRLF => CRLF_COLOR.paint(ending.to_string()),
+        LineEnding::LF => LF_COLOR.paint(ending.to_string()),
+        LineEnding::CR => CR_COLOR.paint(ending.to_string()),
+    }
+    .to_string()
+}
+
+fn percent(value: usize, total: usize) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        value as f64 * 100.0 / total as f64
+    }
+}
+
+/// The files to work on, all listed before starting or found one by one with --stream
+enum Files<'a> {
+    Listed(&'a [PathBuf]),
+    Walked(Box<dyn Iterator<Item = anyhow::Result<PathBuf>> + 'a>),
+}
+
+/// Run `process` on every file and hand the results to `report` in input order
+///
+/// With a single job or walked files every result is reported as soon as it's
+/// ready, otherwise the files are processed on a thread pool first.
+/// Binary files are skipped unless `--force-binary` is given, see [`skipped_content`].
+/// A file that fails is reported on stderr and the rest are still processed,
+/// the error returned at the end counts them. `--fail-fast` stops at the first one instead.
+fn for_each_file<T, P, R>(files: Files, args: &Cli, process: P, mut report: R) -> anyhow::Result<()>
+where
+    T: Send,
+    P: Fn(&Path) -> anyhow::Result<T> + Sync,
+    R: FnMut(&Path, T) -> anyhow::Result<()>,
+{
+    let process = |f: &Path| {
+        if let Some(content) = skipped_content(f, args)? {
+            return Ok(Err(content));
+        }
+        process(f).map(Ok)
+    };
+    let mut failed = 0;
+    let mut report = |f: &Path, result: anyhow::Result<Result<T, Content>>| {
+        let result = match result {
+            Ok(Ok(result)) => report(f, result),
+            Ok(Err(content)) => {
+                eprintln!("skipped {content} file {}", f.display());
+                Ok(())
+            }
+            Err(e) => Err(e),
+        };
+        match result {
+            Err(e) if !args.fail_fast => {
+                eprintln!("Error: {e:#}");
+                failed += 1;
+                Ok(())
+            }
+            result => result,
+        }
+    };
+
+    // the length of a walk isn't known, so it never gets a bar
+    let progress = match files {
+        Files::Listed(files) => progress_bar(files.len(), args),
+        Files::Walked(_) => ProgressBar::hidden(),
+    };
+    let process = |f: &Path| {
+        progress.set_message(f.display().to_string());
+        let result = process(f);
+        progress.inc(1);
+        result
+    };
+    let mut report = |f: &Path, result| progress.suspend(|| report(f, result));
+
+    let total = match files {
+        Files::Listed(files) if args.jobs == 1 => {
+            files.iter().try_for_each(|f| report(f, process(f)))?;
+            files.len()
+        }
+        Files::Listed(files) => {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(args.jobs)
+                .build()
+                .context("Create thread pool failed")?;
+            let results: Vec<_> = pool.install(|| files.par_iter().map(|f| process(f)).collect());
+            files
+                .iter()
+                .zip(results)
+                .try_for_each(|(f, result)| report(f, result))?;
+            files.len()
+        }
+        Files::Walked(walk) => {
+            let mut total = 0;
+            for f in walk {
+                let f = f?;
+                report(&f, process(&f))?;
+                total += 1;
+            }
+            total
+        }
+    };
+    progress.finish_and_clear();
+    if failed != 0 {
+        return Err(anyhow!("{failed} of {total} files failed"));
+    }
+    Ok(())
+}
+
+/// Runs with fewer files finish too fast for a progress bar to be of any use
+const PROGRESS_MIN_FILES: usize = 100;
+
+/// Exit codes of the check actions with --detailed-exit-code, by what the
+/// offending files use, a higher one wins
+const EXIT_LF: u8 = 2;
+const EXIT_CRLF: u8 = 3;
+const EXIT_CR: u8 = 4;
+const EXIT_MIXED: u8 = 5;
+
+fn exit_code(kind: FileKind) -> u8 {
+    match kind {
+        FileKind::Pure(LineEnding::LF) => EXIT_LF,
+        FileKind::Pure(LineEnding::CRLF) => EXIT_CRLF,
+        FileKind::Pure(LineEnding::CR) => EXIT_CR,
+        FileKind::Mixed => EXIT_MIXED,
+        FileKind::Empty | FileKind::NoEndings => 0,
+    }
+}
+
+/// Progress bar on stderr, hidden unless both outputs are terminals
+fn progress_bar(len: usize, args: &Cli) -> ProgressBar {
+    if args.quiet
+        || len < PROGRESS_MIN_FILES
+        || !atty::is(Stream::Stdout)
+        || !atty::is(Stream::Stderr)
+    {
+        return ProgressBar::hidden();
+    }
+    ProgressBar::new(len as u64).with_style(
+        ProgressStyle::with_template("{bar:40} {pos}/{len} {wide_msg}")
+            .expect("progress template is valid"),
+    )
+}
+
+/// What the content of `f` was taken for if it's left out, `None` to process it
+///
+/// With --content only those kinds are processed, --force-binary doesn't matter then.
+/// A gzip compressed file is only looked into when measuring, the converting
+/// actions always skip it as binary.
+fn skipped_content(f: &Path, args: &Cli) -> anyhow::Result<Option<Content>> {
+    let decompress = !args.action.converts();
+    if args.content.is_empty() && args.force_binary && decompress {
+        return Ok(None);
+    }
+    let sample = sample(f, decompress)?;
+    if !decompress && sample.starts_with(&GZIP_MAGIC) {
+        return Ok(Some(Content::Binary));
+    }
+    if args.content.is_empty() {
+        if args.force_binary {
+            return Ok(None);
+        }
+        if !args.sniff {
+            return Ok(is_binary(&sample).then_some(Content::Binary));
+        }
+    }
+    let content = sniff(&sample);
+    let skipped = if args.content.is_empty() {
+        content == Content::Binary
+    } else {
+        !args.content.contains(&content)
+    };
+    Ok(skipped.then_some(content))
+}
+
+/// Leading bytes of `f`, decompressed with `decompress`, as much as [`is_binary`]
+/// and [`sniff`] look at
+fn sample(f: &Path, decompress: bool) -> anyhow::Result<Vec<u8>> {
+    let mut sample = Vec::with_capacity(BINARY_SNIFF_LEN);
+    let source: Box<dyn Read> = if decompress {
+        Box::new(open_file(f, BINARY_SNIFF_LEN, true)?)
+    } else {
+        Box::new(File::open(f).context(format!("Read file {} failed", f.display()))?)
+    };
+    source
+        .take(BINARY_SNIFF_LEN as u64)
+        .read_to_end(&mut sample)
+        .context(format!("Read file {} failed", f.display()))?;
+    Ok(sample)
+}
+
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+
+/// Open `f` for reading in blocks of `block_size`
+///
+/// A gzip compressed file is decompressed with `decompress`, and an error otherwise.
+fn open_file(f: &Path, block_size: usize, decompress: bool) -> anyhow::Result<Box<dyn BufRead>> {
+    let mut source = BufReader::with_capacity(
+        block_size,
+        File::open(f).context(format!("Read file {} failed", f.display()))?,
+    );
+    let head = source
+        .fill_buf()
+        .context(format!("Read file {} failed", f.display()))?;
+    if !head.starts_with(&GZIP_MAGIC) {
+        return Ok(Box::new(source));
+    }
+    if !decompress {
+        return Err(anyhow!(
+            "{} is gzip compressed, it can only be measured",
+            f.display()
+        ));
+    }
+    Ok(Box::new(BufReader::with_capacity(
+        block_size,
+        flate2::bufread::MultiGzDecoder::new(source),
+    )))
+}
+
+/// Open `f` for reading, UTF-16 files are recognized by their byte order mark
+///
+/// See [`open_file`] for `decompress`.
+fn open_text(
+    f: &Path,
+    block_size: usize,
+    decompress: bool,
+) -> anyhow::Result<(Box<dyn BufRead>, Option<utf16::ByteOrder>)> {
+    let mut source = open_file(f, block_size, decompress)?;
+    let order = utf16::detect_bom(
+        source
+            .fill_buf()
+            .context(format!("Read file {} failed", f.display()))?,
+    );
+    Ok((source, order))
+}
+
+fn measure(f: &Path, block_size: usize) -> anyhow::Result<CrlfStat> {
+    let (source, order) = open_text(f, block_size, true)?;
+    match order {
+        Some(order) => utf16::measure_file(source, order),
+        None => CrlfStat::measure_fast(source),
+    }
+    .context(format!("Measure file {} failed", f.display()))
+}
+
+fn measure_with(f: &Path, options: &MeasureOptions, block_size: usize) -> anyhow::Result<CrlfStat> {
+    let (source, order) = open_text(f, block_size, true)?;
+    match order {
+        Some(order) => utf16::measure_with(source, order, options),
+        None => CrlfStat::measure_with(source, options),
+    }
+    .context(format!("Measure file {} failed", f.display()))
+}
+
+fn convert_text<W: Write>(
+    f: &Path,
+    dest: W,
+    options: &ConvertOptions,
+    block_size: usize,
+) -> anyhow::Result<CrlfStat> {
+    let (source, order) = open_text(f, block_size, false)?;
+    match order {
+        Some(order) => utf16::convert_with(source, dest, order, options),
+        None => convert_with(source, dest, options),
+    }
+    .context(format!("Convert file {} failed", f.display()))
+}
+
+fn print_stat(
+    f: &Path,
+    stat: &CrlfStat,
+    content: Option<Content>,
+    color: bool,
+    verbose: bool,
+    threshold: Option<f64>,
+) {
+    let paint = |colour: ansi_term::Colour, text: String| {
+        if color {
+            colour.paint(text).to_string()
+        } else {
+            text
+        }
+    };
+    let yes_no = |value: bool| if value { "yes" } else { "no" };
+
+    let (colour, indicator) = match stat.classify() {
+        FileKind::Pure(LineEnding::CRLF) => (CRLF_COLOR, 'C'),
+        FileKind::Pure(LineEnding::LF) => (LF_COLOR, 'L'),
+        FileKind::Pure(LineEnding::CR) => (CR_COLOR, 'R'),
+        FileKind::Mixed => match threshold.and_then(|percent| stat.mostly(percent)) {
+            Some(LineEnding::CRLF) => (CRLF_COLOR, 'c'),
+            Some(LineEnding::LF) => (LF_COLOR, 'l'),
+            Some(LineEnding::CR) => (CR_COLOR, 'r'),
+            None => (MIXED_COLOR, 'X'),
+        },
+        FileKind::Empty => (EMPTY_COLOR, 'E'),
+        FileKind::NoEndings => (EMPTY_COLOR, 'N'),
+    };
+    let mut columns = vec![
+        paint(colour, indicator.to_string()),
+        paint(CRLF_COLOR, format!("crlf: {:4}", stat.crlf())),
+        paint(LF_COLOR, format!("lf: {:4}", stat.lf())),
+        paint(CR_COLOR, format!("cr: {:4}", stat.cr())),
+        paint(
+            if stat.ends_with_newline() {
+                LF_COLOR
             } else {
-                return Err(anyhow!("git exit unexpectly without an exit code"));
+                MIXED_COLOR
+            },
+            format!("final-nl: {:>3}", yes_no(stat.ends_with_newline())),
+        ),
+    ];
+    if verbose {
+        columns.push(format!("bom: {:>3}", yes_no(stat.has_bom())));
+        columns.push(format!("blank: {:4}", stat.blank_lines()));
+    }
+    if let Some(content) = content {
+        columns.push(format!("content: {content}"));
+    }
+    if verbose && stat.unicode_separators() != 0 {
+        columns.push(format!("u2028/9: {}", stat.unicode_separators()));
+    }
+    if stat.interior_cr() != 0 {
+        columns.push(paint(
+            MIXED_COLOR,
+            format!("interior-cr: {}", stat.interior_cr()),
+        ));
+    }
+    columns.push(f.display().to_string());
+    println!("{}", columns.join(", "));
+
+    if verbose && stat.classify() == FileKind::Mixed {
+        // endings in the order they first appear, the second one is where the file diverges
+        let mut firsts: Vec<_> = LineEnding::ALL
+            .into_iter()
+            .filter_map(|ending| Some((stat.first_line(ending)?, ending)))
+            .collect();
+        firsts.sort_unstable_by_key(|(line, _)| *line);
+        if let [(_, first), (line, diverging), ..] = firsts[..] {
+            println!(
+                "  first {} at line {line} in a file starting with {}",
+                paint_ending(diverging, color),
+                paint_ending(first, color)
+            );
+        }
+    }
+}
+
+/// Line ending a file is converted to by the action, `measure` is only called when needed
+fn target_ending<M>(args: &Cli, measure: M) -> anyhow::Result<LineEnding>
+where
+    M: FnOnce() -> anyhow::Result<CrlfStat>,
+{
+    if let Some(ending) = args.action.target().or(args.set_ending) {
+        return Ok(ending);
+    }
+    assert_eq!(args.action, Action::Normalize, "not converting");
+    let stat = measure()?;
+    Ok(stat
+        .is_pure()
+        .unwrap_or_else(|| stat.dominant_or(args.prefer.unwrap_or(LineEnding::LF))))
+}
+
+enum SetOutcome {
+    Unchanged,
+    WouldConvert,
+    /// Diff of what converting would change
+    Diff(String),
+    /// With the kind of file it was before converting, and the lines converted
+    /// by the ending they had, only counted for --verbose and --what-changed
+    Converted {
+        before: FileKind,
+        lines: Vec<(LineEnding, usize)>,
+    },
+}
+
+fn set_ending(
+    f: &Path,
+    target: LineEnding,
+    options: &ConvertOptions,
+    args: &Cli,
+) -> anyhow::Result<SetOutcome> {
+    let options = &options.clone().ending(target);
+    if !would_change(f, options, args.block_size)? {
+        return Ok(SetOutcome::Unchanged);
+    }
+    if args.dry_run {
+        return Ok(SetOutcome::WouldConvert);
+    }
+    if args.diff {
+        let original = std::fs::read(f).context(format!("Read file {} failed", f.display()))?;
+        let mut converted = Vec::with_capacity(original.len());
+        convert_text(f, &mut converted, options, args.block_size)?;
+        let diff = diff::unified(f, &original, &converted, use_color(args));
+        return Ok(SetOutcome::Diff(diff));
+    }
+    if let Some(suffix) = &args.backup {
+        backup(f, suffix, args.force)?;
+    }
+
+    let mtime = if args.preserve_mtime {
+        let metadata =
+            std::fs::metadata(f).context(format!("Read metadata of {} failed", f.display()))?;
+        Some(filetime::FileTime::from_last_modification_time(&metadata))
+    } else {
+        None
+    };
+    let mut stat = CrlfStat::default();
+    write_atomic(f, |dest| {
+        stat = convert_text(f, dest, options, args.block_size)?;
+        anyhow::Ok(())
+    })
+    .context(format!("Write file {} failed", f.display()))?;
+    if let Some(mtime) = mtime {
+        filetime::set_file_mtime(f, mtime).context(format!(
+            "Restore modification time of {} failed",
+            f.display()
+        ))?;
+    }
+    let lines = if args.verbose || args.what_changed {
+        converted_counts(&stat, &measure(f, args.block_size)?, target)
+    } else {
+        vec![]
+    };
+    Ok(SetOutcome::Converted {
+        before: stat.classify(),
+        lines,
+    })
+}
+
+/// Copy `f` to a file with `suffix` appended to its name
+fn backup(f: &Path, suffix: &str, force: bool) -> anyhow::Result<()> {
+    let mut name = f.as_os_str().to_owned();
+    name.push(suffix);
+    let backup = PathBuf::from(name);
+    if !force && backup.symlink_metadata().is_ok() {
+        return Err(anyhow!(
+            "Backup {} already exists, use --force to overwrite it",
+            backup.display()
+        ));
+    }
+    std::fs::copy(f, &backup).context(format!("Write backup {} failed", backup.display()))?;
+    Ok(())
+}
+
+/// Convert `f` without writing it anywhere, just to see if the output differs from the input
+fn would_change(f: &Path, options: &ConvertOptions, block_size: usize) -> anyhow::Result<bool> {
+    let mut compare = CompareWriter {
+        original: BufReader::new(
+            File::open(f).context(format!("Read file {} failed", f.display()))?,
+        ),
+        expected: vec![],
+        differs: false,
+    };
+    convert_text(f, &mut compare, options, block_size)?;
+    Ok(compare.differs || !compare.original.fill_buf()?.is_empty())
+}
+
+/// Sink that compares everything written to it against `original`
+struct CompareWriter<R> {
+    original: R,
+    expected: Vec<u8>,
+    differs: bool,
+}
+
+impl<R: Read> Write for CompareWriter<R> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if !self.differs {
+            self.expected.resize(buf.len(), 0);
+            match self.original.read_exact(&mut self.expected) {
+                Ok(()) => self.differs = self.expected != buf,
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => self.differs = true,
+                Err(e) => return Err(e),
             }
         }
-        String::from_utf8_lossy(&git_result.stdout)
-            .split('\n')
-            .map(|s| s.trim())
-            .filter(|s| !s.is_empty())
-            .map(PathBuf::from)
-            .collect()
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Fill in what the command line leaves open from `config`
+fn apply_config(args: &mut Cli, config: config::Config) {
+    // stdin is converted when no patterns are given, even if the config has some
+    if args.patterns.is_empty() && !(args.action.converts() && stdin_is_piped()) {
+        args.patterns = config.patterns;
+    }
+    args.exclude.extend(config.exclude);
+    args.prefer = args.prefer.or(config.ending);
+    if args.action == Action::Set {
+        args.set_ending = args.set_ending.or(config.ending);
+    }
+    // the --no-... flags win over the config
+    args.respect_gitignore |= config.respect_gitignore && !args.no_respect_gitignore;
+    args.use_gitattributes |= config.use_gitattributes && !args.no_use_gitattributes;
+    args.use_editorconfig |= config.use_editorconfig && !args.no_use_editorconfig;
+}
+
+fn main() -> anyhow::Result<ExitCode> {
+    let start = Instant::now();
+    let mut args = Cli::try_parse_from(std::env::args_os()).unwrap_or_else(|e| e.exit());
+    if args.action == Action::Undo {
+        return undo(&args);
+    }
+    // before the config, which is then found and resolved from the top directory
+    if args.repo_root {
+        let toplevel = git(&args)?.toplevel()?;
+        std::env::set_current_dir(&toplevel)
+            .context(format!("Change to {} failed", toplevel.display()))?;
+    }
+    if !args.no_config {
+        let cwd = std::env::current_dir()?;
+        if let Some(path) = config::find(&cwd) {
+            apply_config(&mut args, config::load(&path, &cwd)?);
+        }
+    }
+    if args.action == Action::Set && args.set_ending.is_none() {
+        Cli::command_line()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "set needs a line ending when .crlf.toml has no `ending`",
+            )
+            .exit();
+    }
+    if args.patterns.is_empty() {
+        if args.action.converts() && stdin_is_piped() {
+            return filter_stdin(&args);
+        }
+        args.patterns.push("**/*".to_string());
+    }
+
+    let match_options = discovery::match_options(args.ignore_case, args.include_hidden);
+    if args.stream {
+        if args.action != Action::Measure || args.patterns == ["-"] {
+            Cli::command_line()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    "--stream only works for measure with glob patterns",
+                )
+                .exit();
+        }
+        return measure_walk(&args, match_options, start);
+    }
+    let files = find_files(&args, match_options)?;
+
+    let result = match args.action {
+        Action::Measure => measure_files(Files::Listed(&files), &args),
+        Action::Set | Action::SetCrlf | Action::SetLf | Action::Normalize => {
+            set_files(&files, &args)
+        }
+        Action::CheckCrlf => check_files(&files, &args, LineEnding::CRLF),
+        Action::CheckLf => check_files(&files, &args, LineEnding::LF),
+        Action::FindMixed => find_mixed(&files, &args),
+        Action::Undo => unreachable!("undo is done before looking for files"),
+    };
+    // on stderr, so it never mixes with a list of paths or the totals
+    if args.verbose && args.format == Format::Text {
+        eprintln!("processed {} files in {:.2?}", files.len(), start.elapsed());
+    }
+    result
+}
+
+/// The files to work on, found by the patterns and filtered by the options
+fn find_files(args: &Cli, match_options: glob::MatchOptions) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files: Vec<PathBuf> = if args.git_file {
+        let mut files = git(args)?.files(&args.patterns, args.ignore_case, args.tracked_only)?;
+        discovery::limit_depth(&mut files, args.max_depth);
+        files
     } else {
-        glob::glob(args.pattern.as_str())
-            .context("Failed to read glob pattern")?
-            .filter(|f| f.as_ref().is_ok_and(|f| f.is_file()))
-            .collect::<Result<Vec<_>, _>>()
-            .context("Glob match error")?
+        let mut files = if args.patterns == ["-"] {
+            let mut files = discovery::stdin_files(args.null)?;
+            discovery::limit_depth(&mut files, args.max_depth);
+            files
+        } else {
+            discovery::glob_files(&args.patterns, args.max_depth, match_options)?
+        };
+        if args.respect_gitignore {
+            let mut filter = discovery::GitignoreFilter::default();
+            let mut kept = Vec::with_capacity(files.len());
+            for f in files {
+                if !filter.is_ignored(&f)? {
+                    kept.push(f);
+                }
+            }
+            files = kept;
+        }
+        files
     };
+    discovery::exclude(&mut files, &args.exclude, match_options)?;
+    discovery::symlinks(&mut files, args.follow_symlinks);
+    if args.relative || args.absolute {
+        discovery::rewrite_paths(&mut files, &std::env::current_dir()?, args.absolute);
+    }
+    Ok(files)
+}
 
-    if args.action == Action::Measure {
-        files.iter().try_for_each(|f| {
-            let stat = CrlfStat::measure_file(BufReader::new(
-                File::open(f).context(format!("Read file {} failed", f.display()))?,
-            ))
-            .context(format!("Measure file {} failed", f.display()))?;
-            if atty::is(Stream::Stdout) {
-                let indicator = match stat.is_pure() {
-                    Some(LineEnding::CRLF) => CRLF_COLOR.paint("C"),
-                    Some(LineEnding::LF) => LF_COLOR.paint("L"),
-                    None => MIXED_COLOR.paint("X"),
+fn git(args: &Cli) -> anyhow::Result<discovery::Git> {
+    discovery::Git::new(&args.git_bin, &args.git_arg)
+}
+
+/// measure --stream
+fn measure_walk(
+    args: &Cli,
+    match_options: glob::MatchOptions,
+    start: Instant,
+) -> anyhow::Result<ExitCode> {
+    let found = Cell::new(0);
+    let walk = walk_files(args, match_options)?.inspect(|_| found.set(found.get() + 1));
+    let result = measure_files(Files::Walked(Box::new(walk)), args);
+    if args.verbose && args.format == Format::Text {
+        eprintln!("processed {} files in {:.2?}", found.get(), start.elapsed());
+    }
+    result
+}
+
+/// Same files as [`find_files`] for glob patterns, each filtered as soon as the walk finds it
+fn walk_files(
+    args: &Cli,
+    match_options: glob::MatchOptions,
+) -> anyhow::Result<impl Iterator<Item = anyhow::Result<PathBuf>> + '_> {
+    let excludes = discovery::exclude_patterns(&args.exclude)?;
+    let mut gitignore = args
+        .respect_gitignore
+        .then(discovery::GitignoreFilter::default);
+    let mut symlinks = discovery::Symlinks::new(args.follow_symlinks);
+    let cwd = std::env::current_dir()?;
+    let mut keep = move |f: PathBuf| -> anyhow::Result<Option<PathBuf>> {
+        if let Some(filter) = &mut gitignore {
+            if filter.is_ignored(&f)? {
+                return Ok(None);
+            }
+        }
+        if discovery::is_excluded(&f, &excludes, match_options) {
+            return Ok(None);
+        }
+        let Some(mut f) = symlinks.resolve(f) else {
+            return Ok(None);
+        };
+        if args.relative || args.absolute {
+            discovery::rewrite_paths(std::slice::from_mut(&mut f), &cwd, args.absolute);
+        }
+        Ok(Some(f))
+    };
+    Ok(
+        discovery::glob_walk(&args.patterns, args.max_depth, match_options)
+            .filter_map(move |f| f.and_then(&mut keep).transpose()),
+    )
+}
+
+fn use_color(args: &Cli) -> bool {
+    if args.format != Format::Text {
+        return false;
+    }
+    match args.color {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => {
+            // https://no-color.org
+            std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty()) && atty::is(Stream::Stdout)
+        }
+    }
+}
+
+fn measure_files(files: Files, args: &Cli) -> anyhow::Result<ExitCode> {
+    let color = use_color(args);
+    let mut summary = MeasureSummary::default();
+    let mut by_extension: BTreeMap<String, MeasureSummary> = BTreeMap::new();
+    let mut records = vec![];
+    let mut sorted = vec![];
+    let options = MeasureOptions::new()
+        .unicode_separators(args.unicode_separators)
+        .blank_lines(args.verbose);
+    let measure = |f: &Path| {
+        let stat = if args.unicode_separators || args.verbose {
+            measure_with(f, &options, args.block_size)?
+        } else {
+            measure(f, args.block_size)?
+        };
+        let content = if args.verbose && args.format == Format::Text {
+            Some(sniff(&sample(f, true)?))
+        } else {
+            None
+        };
+        let sha256 = if args.with_hash && args.format == Format::Json {
+            Some(journal::sha256_file(f)?)
+        } else {
+            None
+        };
+        anyhow::Ok((stat, content, sha256))
+    };
+    let result = for_each_file(files, args, measure, |f, (stat, content, sha256)| {
+        if args.quiet && stat.classify() != FileKind::Mixed {
+            return Ok(());
+        }
+        summary.add(&stat);
+        if args.by_extension {
+            by_extension.entry(extension_key(f)).or_default().add(&stat);
+        }
+        if args.summary_only {
+            return Ok(());
+        }
+        if args.sort.is_some() {
+            sorted.push((f.to_path_buf(), stat, (content, sha256)));
+        } else if args.format == Format::Json {
+            records.push(MeasureRecord::new(f, &stat, sha256));
+        } else {
+            print_stat(f, &stat, content, color, args.verbose, args.threshold);
+        }
+        Ok(())
+    });
+    if let Some(key) = args.sort {
+        key.sort(&mut sorted);
+        for (f, stat, (content, sha256)) in sorted {
+            if args.format == Format::Json {
+                records.push(MeasureRecord::new(&f, &stat, sha256));
+            } else {
+                print_stat(&f, &stat, content, color, args.verbose, args.threshold);
+            }
+        }
+    }
+    // failed files are left out, the totals of the others are still printed
+    // before the failure is returned
+    match args.format {
+        Format::Json if args.by_extension => {
+            let totals: BTreeMap<_, _> = by_extension
+                .iter()
+                .map(|(ext, summary)| (ext, summary.totals()))
+                .collect();
+            println!("{}", serde_json::to_string_pretty(&totals)?);
+        }
+        Format::Json if args.summary_only => println!("{}", summary.to_json()?),
+        Format::Json => println!("{}", serde_json::to_string_pretty(&records)?),
+        _ if !args.quiet => {
+            for (ext, summary) in &by_extension {
+                summary.print_files(ext, color, args.verbose);
+            }
+            summary.print(color, args.verbose);
+        }
+        _ => {}
+    }
+    result?;
+    if args.fail_on_mixed && summary.mixed_files != 0 {
+        return Ok(ExitCode::from(EXIT_MIXED));
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Conversion asked for on the command line, the ending is set per file
+fn convert_options(args: &Cli) -> ConvertOptions {
+    let options = ConvertOptions::new(LineEnding::LF)
+        .ensure_final_newline(args.ensure_final_newline)
+        .strip_trailing_ws(args.strip_trailing_ws)
+        .bom(match (args.strip_bom, args.add_bom) {
+            (true, _) => Bom::Strip,
+            (_, true) => Bom::Add,
+            _ => Bom::Preserve,
+        })
+        .preserve_shebang_lf(args.preserve_shebang_lf)
+        .lone_cr(args.lone_cr)
+        .replace_unicode_separators(args.unicode_separators)
+        .indent(match (args.tabs_to_spaces, args.spaces_to_tabs) {
+            (Some(width), _) => Indent::Spaces(width),
+            (_, Some(width)) => Indent::Tabs(width),
+            _ => Indent::Keep,
+        });
+    match &args.lines {
+        Some(lines) => options.lines(lines.clone()),
+        None => options,
+    }
+}
+
+/// Convert stdin to stdout, for when the tool is used as a filter in a pipeline
+///
+/// Nothing but the converted text is printed, binary input is passed through as is.
+fn filter_stdin(args: &Cli) -> anyhow::Result<ExitCode> {
+    filter(std::io::stdin().lock(), std::io::stdout().lock(), args)
+}
+
+/// [`filter_stdin`] from `source` to `dest`
+///
+/// The input is streamed when the action gives the ending. Only normalize and
+/// --strict read all of it first, to measure it before converting.
+fn filter<R: BufRead, W: Write>(
+    mut source: R,
+    mut dest: W,
+    args: &Cli,
+) -> anyhow::Result<ExitCode> {
+    let mut head = Vec::with_capacity(BINARY_SNIFF_LEN);
+    (&mut source)
+        .take(BINARY_SNIFF_LEN as u64)
+        .read_to_end(&mut head)
+        .context("Read stdin failed")?;
+    if !args.force_binary && is_binary(&head) {
+        dest.write_all(&head).context("Write stdout failed")?;
+        std::io::copy(&mut source, &mut dest).context("Copy stdin to stdout failed")?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let order = utf16::detect_bom(&head);
+    let known = args.action.target().or(args.set_ending);
+    let (input, target): (Box<dyn BufRead + '_>, _) = match known {
+        Some(target) if !strict(args) => (Box::new(Cursor::new(head).chain(source)), target),
+        _ => {
+            let mut input = head;
+            source
+                .read_to_end(&mut input)
+                .context("Read stdin failed")?;
+            let measure = || {
+                anyhow::Ok(match order {
+                    Some(order) => utf16::measure_file(input.as_slice(), order)?,
+                    None => CrlfStat::measure_fast(input.as_slice())?,
+                })
+            };
+            let stat = if strict(args) {
+                Some(check_strict(Path::new("stdin"), measure()?)?)
+            } else {
+                None
+            };
+            let target = target_ending(args, || stat.map_or_else(measure, Ok))?;
+            (Box::new(Cursor::new(input)), target)
+        }
+    };
+    let options = convert_options(args).ending(target);
+    match order {
+        Some(order) => utf16::convert_with(input, &mut dest, order, &options),
+        None => convert_with(input, &mut dest, &options),
+    }
+    .context("Write stdout failed")?;
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Whether stdin is a pipe or a redirected file, rather than a terminal or nothing at all
+#[cfg(unix)]
+fn stdin_is_piped() -> bool {
+    use std::os::{fd::AsFd, unix::fs::FileTypeExt};
+    std::io::stdin()
+        .as_fd()
+        .try_clone_to_owned()
+        .map(File::from)
+        .and_then(|stdin| stdin.metadata())
+        .is_ok_and(|m| m.file_type().is_fifo() || m.is_file())
+}
+
+#[cfg(not(unix))]
+fn stdin_is_piped() -> bool {
+    !atty::is(Stream::Stdin)
+}
+
+fn set_files(files: &[PathBuf], args: &Cli) -> anyhow::Result<ExitCode> {
+    let color = use_color(args);
+    let options = convert_options(args);
+    let declared = if args.use_gitattributes {
+        attributes::gitattributes(&git(args)?, files)?
+    } else {
+        HashMap::new()
+    };
+
+    let plan = args.dry_run && args.format == Format::Json;
+    let mut entries = vec![];
+    let mut journal = match &args.journal {
+        Some(path) if !args.dry_run && !args.diff => {
+            Some(journal::Journal::create(path, args.force)?)
+        }
+        _ => None,
+    };
+    let journaling = journal.is_some();
+    let mut converted_lines = 0;
+    let mut converted_files = 0;
+    let mut would_convert = 0;
+    let mut tally = ChangeTally::default();
+    let result = for_each_file(
+        Files::Listed(files),
+        args,
+        |f| {
+            let declared = match declared.get(f) {
+                Some(declared) => Some(*declared),
+                None if args.use_editorconfig => attributes::editorconfig(f)?,
+                None => None,
+            };
+            if declared == Some(Declared::NotText) {
+                return Ok(None);
+            }
+            let stat = if strict(args) {
+                Some(check_strict(f, measure(f, args.block_size)?)?)
+            } else if plan {
+                Some(measure(f, args.block_size)?)
+            } else {
+                None
+            };
+            let kind = stat.as_ref().map(CrlfStat::classify);
+            let target = match declared {
+                Some(Declared::Ending(ending)) => ending,
+                _ => target_ending(args, || {
+                    stat.map_or_else(|| measure(f, args.block_size), Ok)
+                })?,
+            };
+            // read before converting, a mixed file is journalled line by line
+            let runs = if journaling {
+                ending_runs(f, args.block_size)?
+            } else {
+                None
+            };
+            let outcome = set_ending(f, target, &options, args)?;
+            Ok(Some((target, outcome, kind, runs)))
+        },
+        |f, mut result| {
+            if matches!(result, Some((_, SetOutcome::WouldConvert, _, _))) {
+                would_convert += 1;
+            }
+            if plan {
+                entries.push(match &result {
+                    Some((target, outcome, kind, _)) => PlanEntry {
+                        path: f.display().to_string(),
+                        kind: kind.map(|kind| kind.to_string()).unwrap_or_default(),
+                        target: Some(target.to_string()),
+                        change: matches!(outcome, SetOutcome::WouldConvert),
+                    },
+                    None => PlanEntry {
+                        path: f.display().to_string(),
+                        kind: "not-text".to_string(),
+                        target: None,
+                        change: false,
+                    },
+                });
+                return Ok(());
+            }
+            if let (Some(journal), Some((_, SetOutcome::Converted { before, .. }, _, runs))) =
+                (&mut journal, &mut result)
+            {
+                match (*before, runs.take()) {
+                    (FileKind::Mixed, Some(runs)) => journal.record(f, "mixed", runs)?,
+                    (FileKind::Mixed, None) => eprintln!(
+                        "Warning: {} is a mixed UTF-16 file, left out of the journal as undo can't restore it",
+                        f.display()
+                    ),
+                    (kind, _) => journal.record(f, &kind.to_string(), vec![])?,
+                }
+            }
+            if let Some((target, outcome, _, _)) = &result {
+                tally.files += 1;
+                if let SetOutcome::Converted { lines, .. } = outcome {
+                    tally.add_converted(lines, *target);
+                }
+            }
+            let Some((target, outcome, _, _)) = result else {
+                if !args.quiet && !args.diff {
+                    println!("{} is not text by .gitattributes, left alone", f.display());
+                }
+                return Ok(());
+            };
+            let ending = paint_ending(target, color);
+            match outcome {
+                SetOutcome::Unchanged if args.quiet || args.diff => {}
+                SetOutcome::Unchanged if args.verbose => {
+                    println!("{} is already {}, unchanged", f.display(), ending)
+                }
+                SetOutcome::Unchanged => println!("{} is already {}", f.display(), ending),
+                SetOutcome::WouldConvert => println!("would set {} to {}", f.display(), ending),
+                SetOutcome::Diff(diff) => print!("{diff}"),
+                SetOutcome::Converted { lines, .. } if args.verbose => {
+                    let converted = lines;
+                    converted_lines += converted.iter().map(|(_, count)| count).sum::<usize>();
+                    converted_files += 1;
+                    let converted: Vec<String> = converted
+                        .iter()
+                        .map(|(ending, count)| format!("{count} {ending}"))
+                        .collect();
+                    let converted = if converted.is_empty() {
+                        "no line ending changed".to_string()
+                    } else {
+                        format!("converted {} lines", converted.join(", "))
+                    };
+                    println!("set {} to {}, {converted}", f.display(), ending);
+                }
+                SetOutcome::Converted { .. } => println!("set {} to {}", f.display(), ending),
+            }
+            Ok(())
+        },
+    );
+    if args.verbose && !args.dry_run && !args.diff {
+        println!("converted {converted_lines} lines in {converted_files} files");
+    }
+    if args.what_changed {
+        println!("{tally}");
+    }
+    if plan {
+        let plan = Plan {
+            version: PLAN_VERSION,
+            files: entries,
+        };
+        println!("{}", serde_json::to_string_pretty(&plan)?);
+    }
+    result?;
+    if args.check_only && would_convert > 0 {
+        return Ok(ExitCode::FAILURE);
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Convert the files in the --journal back to the line endings they had
+fn undo(args: &Cli) -> anyhow::Result<ExitCode> {
+    let Some(path) = &args.journal else {
+        Cli::command_line()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "undo needs the journal of the run to undo, like `crlf undo --journal FILE`",
+            )
+            .exit();
+    };
+    let mut failed = 0;
+    // the files converted last are restored first
+    for entry in journal::read(path)?.iter().rev() {
+        match undo_entry(entry, args) {
+            Ok(ending) if !args.quiet => {
+                let ending = match ending {
+                    Some(ending) => paint_ending(ending, use_color(args)),
+                    None => "its mixed line endings".to_string(),
                 };
-                println!(
-                    "{}, {}, {}, {}",
-                    indicator,
-                    CRLF_COLOR.paint(format!("crlf: {:4}", stat.crlf())),
-                    LF_COLOR.paint(format!("lf: {:4}", stat.lf())),
-                    f.display(),
-                );
+                let done = if args.dry_run {
+                    "would restore"
+                } else {
+                    "restored"
+                };
+                println!("{done} {} to {ending}", entry.path.display())
+            }
+            Ok(_) => {}
+            Err(e) if !args.fail_fast => {
+                eprintln!("Error: {e:#}");
+                failed += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    if failed != 0 {
+        return Err(anyhow!("{failed} files couldn't be restored"));
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Convert the file of `entry` back, unless --dry-run
+///
+/// Returns the line ending it's restored to, `None` for the lines of a mixed file.
+fn undo_entry(entry: &journal::Entry, args: &Cli) -> anyhow::Result<Option<LineEnding>> {
+    let f = &entry.path;
+    let ending = match entry.ending.parse() {
+        Ok(ending) => Some(ending),
+        Err(_) if entry.ending == FileKind::Mixed.to_string() && !entry.runs.is_empty() => None,
+        Err(_) => {
+            return Err(anyhow!(
+                "{} was {} before, and the journal doesn't have the ending of its lines",
+                f.display(),
+                entry.ending
+            ))
+        }
+    };
+    if journal::sha256_file(f)? != entry.sha256 {
+        return Err(anyhow!(
+            "{} changed since it was converted, left alone",
+            f.display()
+        ));
+    }
+    let Some(ending) = ending else {
+        let endings = entry.line_endings()?;
+        let stat = measure(f, args.block_size)?;
+        let lines = stat.crlf() + stat.lf() + stat.cr();
+        if lines != endings.len() {
+            return Err(anyhow!(
+                "{} has {lines} lines now but had {} before, left alone",
+                f.display(),
+                endings.len()
+            ));
+        }
+        if !args.dry_run {
+            let source =
+                BufReader::new(File::open(f).context(format!("Read file {} failed", f.display()))?);
+            let options = ConvertOptions::new(LineEnding::LF);
+            write_atomic(f, |dest| {
+                convert_with_selector(source, dest, &options, |line, _| {
+                    endings.get(line - 1).copied().unwrap_or(LineEnding::LF)
+                })?;
+                anyhow::Ok(())
+            })
+            .context(format!("Write file {} failed", f.display()))?;
+        }
+        return Ok(None);
+    };
+    if !args.dry_run {
+        convert_file_in_place(f, ending)?;
+    }
+    Ok(Some(ending))
+}
+
+/// The endings of the lines of `f` as runs for the journal, `None` for a UTF-16 file
+fn ending_runs(f: &Path, block_size: usize) -> anyhow::Result<Option<Vec<(String, usize)>>> {
+    let (source, order) = open_text(f, block_size, false)?;
+    if order.is_some() {
+        return Ok(None);
+    }
+    let mut endings = vec![];
+    for line in LineEndings::new(source) {
+        let (_, ending) = line.context(format!("Read file {} failed", f.display()))?;
+        endings.extend(ending);
+    }
+    Ok(Some(journal::runs(endings)))
+}
+
+/// Whether --strict applies
+fn strict(args: &Cli) -> bool {
+    args.strict && args.lone_cr == LoneCr::Ending
+}
+
+/// Fail if `stat` of `f` has interior CRs, for --strict
+fn check_strict(f: &Path, stat: CrlfStat) -> anyhow::Result<CrlfStat> {
+    match stat.interior_cr() {
+        0 => Ok(stat),
+        count => Err(anyhow!(
+            "{} has lone CRs between other line endings ({count}), left alone by --strict",
+            f.display()
+        )),
+    }
+}
+
+/// Lines of a file converted to `target`, by the ending they had
+///
+/// Counted from the endings `before` and `after` converting, as --lines and
+/// --lone-cr preserve leave some of the other endings alone.
+fn converted_counts(
+    before: &CrlfStat,
+    after: &CrlfStat,
+    target: LineEnding,
+) -> Vec<(LineEnding, usize)> {
+    LineEnding::ALL
+        .into_iter()
+        .filter(|ending| *ending != target)
+        .map(|ending| {
+            (
+                ending,
+                before.count(ending).saturating_sub(after.count(ending)),
+            )
+        })
+        .filter(|(_, count)| *count != 0)
+        .collect()
+}
+
+/// Print the path of every mixed file, fails if there's any
+///
+/// Paths are terminated by NUL with `--null`, by newline otherwise.
+fn find_mixed(files: &[PathBuf], args: &Cli) -> anyhow::Result<ExitCode> {
+    let mut found = 0;
+    let mut stdout = std::io::stdout().lock();
+    for_each_file(
+        Files::Listed(files),
+        args,
+        |f| measure(f, args.block_size),
+        |f, stat| {
+            if stat.classify() == FileKind::Mixed {
+                found += 1;
+                stdout.write_all(f.as_os_str().as_encoded_bytes())?;
+                stdout.write_all(if args.null { b"\0" } else { b"\n" })?;
+            }
+            Ok(())
+        },
+    )?;
+    if found == 0 {
+        Ok(ExitCode::SUCCESS)
+    } else {
+        Ok(ExitCode::FAILURE)
+    }
+}
+
+/// List the files not purely using `expected`, fails if there's any
+fn check_files(files: &[PathBuf], args: &Cli, expected: LineEnding) -> anyhow::Result<ExitCode> {
+    let color = use_color(args);
+    let options = MeasureOptions::new().record_violations(expected, args.max_violations);
+    let mut offenders = 0;
+    let mut code = 0;
+    let result = for_each_file(
+        Files::Listed(files),
+        args,
+        |f| measure_with(f, &options, args.block_size),
+        |f, stat| {
+            if !stat.needs_conversion(expected) {
+                return Ok(());
+            }
+            offenders += 1;
+            code = code.max(exit_code(stat.classify()));
+            if matches!(args.format, Format::Github | Format::Parseable) {
+                for violation in stat.violations() {
+                    let message = format!(
+                        "wrong line ending {}, expected {expected}",
+                        violation.ending
+                    );
+                    if args.format == Format::Github {
+                        println!(
+                            "::error file={},line={}::{message}",
+                            f.display(),
+                            violation.line
+                        );
+                    } else {
+                        println!("{}:{}: {message}", f.display(), violation.line);
+                    }
+                }
             } else {
-                let indicator = match stat.is_pure() {
-                    Some(LineEnding::CRLF) => 'C',
-                    Some(LineEnding::LF) => 'L',
-                    None => 'X',
+                let kind = stat.classify();
+                let kind = match kind {
+                    FileKind::Pure(ending) => paint_ending(ending, color),
+                    FileKind::Mixed if color => MIXED_COLOR.paint(kind.to_string()).to_string(),
+                    _ => kind.to_string(),
                 };
                 println!(
-                    "{}, crlf: {:4}, lf: {:4}, {}",
-                    indicator,
-                    stat.crlf(),
-                    stat.lf(),
+                    "{}: {}, expected {}",
                     f.display(),
+                    kind,
+                    paint_ending(expected, color)
                 );
             }
-            Ok::<(), anyhow::Error>(())
-        })?;
+            Ok(())
+        },
+    );
+
+    if offenders != 0 {
+        eprintln!(
+            "{offenders} of {} files don't use {expected} line endings",
+            files.len()
+        );
+    }
+    result?;
+    if offenders == 0 {
+        Ok(ExitCode::SUCCESS)
+    } else if args.detailed_exit_code {
+        Ok(ExitCode::from(code))
     } else {
-        let target = match args.action {
-            Action::SetCrlf => LineEnding::CRLF,
-            Action::SetLf => LineEnding::LF,
-            _ => unreachable!("wtf"),
+        Ok(ExitCode::FAILURE)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    fn parse(args: &[&str]) -> Cli {
+        Cli::try_parse_from(std::iter::once("crlf").chain(args.iter().copied())).unwrap()
+    }
+
+    #[test]
+    fn test_command_line() {
+        Cli::command_line().debug_assert();
+
+        let args = parse(&["-v", "set", "crlf", "a", "--dry-run", "b"]);
+        assert_eq!(args.action, Action::Set);
+        assert_eq!(args.set_ending, Some(LineEnding::CRLF));
+        assert_eq!(args.patterns, ["a", "b"]);
+        assert!(args.verbose && args.dry_run);
+
+        let args = parse(&["set-lf", "a"]);
+        assert_eq!(args.action, Action::SetLf);
+        assert_eq!(args.set_ending, None);
+        assert_eq!(args.patterns, ["a"]);
+
+        let err = Cli::try_parse_from(["crlf", "set", "a.txt"]).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidValue);
+    }
+
+    #[test]
+    fn test_apply_config() {
+        let config = || config::Config {
+            ending: Some(LineEnding::CRLF),
+            respect_gitignore: true,
+            use_gitattributes: true,
+            use_editorconfig: true,
+            ..Default::default()
         };
+        let mut args = parse(&["set", "--no-respect-gitignore", "--no-use-editorconfig"]);
+        apply_config(&mut args, config());
+        assert_eq!(args.set_ending, Some(LineEnding::CRLF));
+        assert!(!args.respect_gitignore && !args.use_editorconfig);
+        assert!(args.use_gitattributes);
 
-        files.iter().try_for_each(|f| {
-            let mut dest = vec![];
-            convert_to(
-                BufReader::new(File::open(f).context(format!("Read file {} failed", f.display()))?),
-                &mut dest,
-                target,
-            )
-            .context(format!("Convert file {} failed", f.display()))?;
-            std::fs::write(f, dest).context(format!("Write file {} failed", f.display()))?;
+        let mut args = parse(&["set", "lf"]);
+        apply_config(&mut args, config());
+        assert_eq!(args.set_ending, Some(LineEnding::LF));
+        assert!(args.respect_gitignore && args.use_gitattributes && args.use_editorconfig);
 
-            if atty::is(Stream::Stdout) {
-                println!(
-                    "set {} to {}",
-                    f.display(),
-                    match target {
-                        LineEnding::CRLF => CRLF_COLOR.paint(format!("{}", target)),
-                        LineEnding::LF => LF_COLOR.paint(format!("{}", target)),
-                    }
-                );
-            } else {
-                println!("set {} to {}", f.display(), target);
+        let mut args = parse(&["measure"]);
+        apply_config(&mut args, config());
+        assert_eq!(args.set_ending, None);
+        assert_eq!(args.prefer, Some(LineEnding::CRLF));
+    }
+
+    #[test]
+    fn test_config_from_subdirectory() {
+        let dir = TestDir::new("config-subdir");
+        let sub = dir.join("sub");
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::create_dir(&sub).unwrap();
+        std::fs::write(dir.join("src/a.txt"), "a").unwrap();
+        std::fs::write(dir.join("src/skip.txt"), "skip").unwrap();
+        std::fs::write(
+            dir.join(config::FILE_NAME),
+            "patterns = [\"src/*.txt\"]\nexclude = [\"src/skip.txt\"]\n",
+        )
+        .unwrap();
+
+        let path = config::find(&sub).unwrap();
+        let mut args = parse(&["measure"]);
+        apply_config(&mut args, config::load(&path, &sub).unwrap());
+        assert_eq!(args.patterns, ["../src/*.txt"]);
+
+        // glob from sub without changing the current directory of the tests
+        let pattern = sub.join(&args.patterns[0]);
+        let mut files: Vec<PathBuf> = glob::glob(&pattern.to_string_lossy())
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(files.len(), 2);
+        discovery::exclude(&mut files, &args.exclude, glob::MatchOptions::new()).unwrap();
+        assert_eq!(files, [sub.join("../src/a.txt")]);
+    }
+
+    #[test]
+    fn test_undo_mixed() {
+        let dir = TestDir::new("undo");
+        let f = dir.join("mixed.txt");
+        let journal = dir.join("journal");
+        let mixed = b"a\r\nb\r\nc\nd\re".as_slice();
+        std::fs::write(&f, mixed).unwrap();
+
+        let path = f.display().to_string();
+        let journal_arg = journal.display().to_string();
+        let args = parse(&["set", "lf", &path, "--journal", &journal_arg, "-q"]);
+        set_files(std::slice::from_ref(&f), &args).unwrap();
+        assert_eq!(std::fs::read(&f).unwrap(), b"a\nb\nc\nd\ne");
+
+        let args = parse(&["undo", "--journal", &journal_arg, "--dry-run", "-q"]);
+        undo(&args).unwrap();
+        assert_eq!(std::fs::read(&f).unwrap(), b"a\nb\nc\nd\ne");
+        let args = parse(&["undo", "--journal", &journal_arg, "-q"]);
+        undo(&args).unwrap();
+        assert_eq!(std::fs::read(&f).unwrap(), mixed);
+    }
+
+    /// What converting `input` with `options` counts as converted
+    fn converted(input: &[u8], options: &ConvertOptions) -> Vec<(LineEnding, usize)> {
+        let mut output = vec![];
+        let before = convert_with(input, &mut output, options).unwrap();
+        let after = CrlfStat::measure_fast(output.as_slice()).unwrap();
+        converted_counts(&before, &after, LineEnding::LF)
+    }
+
+    #[test]
+    fn test_converted_counts() {
+        let input = b"a\r\nb\rc\r\nd\n".as_slice();
+        let options = ConvertOptions::new(LineEnding::LF);
+        assert_eq!(
+            converted(input, &options),
+            [(LineEnding::CRLF, 2), (LineEnding::CR, 1)]
+        );
+        let options = options.lone_cr(LoneCr::Preserve);
+        assert_eq!(converted(input, &options), [(LineEnding::CRLF, 2)]);
+        assert_eq!(converted(b"a\n", &options), []);
+    }
+
+    #[test]
+    fn test_converted_counts_lines() {
+        let input = b"a\r\nb\r\nc\rd\r\n".as_slice();
+        let options = ConvertOptions::new(LineEnding::LF).lines(2..=3);
+        assert_eq!(
+            converted(input, &options),
+            [(LineEnding::CRLF, 1), (LineEnding::CR, 1)]
+        );
+        let options = options.lines(5..=9);
+        assert_eq!(converted(input, &options), []);
+    }
+
+    #[test]
+    fn test_change_tally() {
+        let mut tally = ChangeTally::default();
+        let unchanged = ConvertOptions::new(LineEnding::LF);
+        let ranged = unchanged.clone().lines(1..=2);
+        let preserved = unchanged.clone().lone_cr(LoneCr::Preserve);
+        for (input, options) in [
+            (b"a\n".as_slice(), &unchanged),
+            (b"a\r\nb\r\nc\r\n", &ranged),
+            (b"a\r\nb\rc\n", &preserved),
+        ] {
+            tally.files += 1;
+            let lines = converted(input, options);
+            if !lines.is_empty() {
+                tally.add_converted(&lines, LineEnding::LF);
             }
+        }
+        tally.files += 1;
+        tally.add_converted(&[(LineEnding::LF, 2)], LineEnding::CRLF);
+        assert_eq!(
+            tally.to_string(),
+            "changed 3 of 4 files (3 crlf→lf, 2 lf→crlf)"
+        );
+        assert_eq!(ChangeTally::default().to_string(), "changed 0 of 0 files");
+    }
+
+    #[test]
+    fn test_for_each_file_failures() {
+        let lf = PathBuf::from("test/Cargo.toml.lf");
+        let crlf = PathBuf::from("test/Cargo.toml.crlf");
+        let files = [lf.clone(), PathBuf::from("test/missing"), crlf.clone()];
+        let run = |args: &[&str]| {
+            let mut reported = vec![];
+            let result = for_each_file(
+                Files::Listed(&files),
+                &parse(args),
+                |f| measure(f, DEFAULT_BLOCK_SIZE),
+                |f, _| {
+                    reported.push(f.to_path_buf());
+                    Ok(())
+                },
+            );
+            (result.map_err(|e| e.to_string()), reported)
+        };
 
-            Ok::<(), anyhow::Error>(())
-        })?;
+        for jobs in ["1", "2"] {
+            let (result, reported) = run(&["measure", "--jobs", jobs]);
+            assert_eq!(result.unwrap_err(), "1 of 3 files failed");
+            assert_eq!(reported, [lf.clone(), crlf.clone()]);
+        }
+        let (result, reported) = run(&["measure", "--fail-fast"]);
+        assert!(result.unwrap_err().contains("test/missing"));
+        assert_eq!(reported, std::slice::from_ref(&lf));
     }
 
-    Ok(())
+    #[test]
+    fn test_detailed_exit_code() {
+        let check = |names: &[&str], expected, detailed| {
+            let files: Vec<PathBuf> = names
+                .iter()
+                .map(|name| PathBuf::from(format!("test/Cargo.toml.{name}")))
+                .collect();
+            let args = if detailed {
+                parse(&["check-lf", "-q", "--detailed-exit-code"])
+            } else {
+                parse(&["check-lf", "-q"])
+            };
+            check_files(&files, &args, expected).unwrap()
+        };
+        assert_eq!(check(&["lf"], LineEnding::LF, true), ExitCode::SUCCESS);
+        assert_eq!(
+            check(&["lf"], LineEnding::CRLF, true),
+            ExitCode::from(EXIT_LF)
+        );
+        assert_eq!(
+            check(&["crlf"], LineEnding::LF, true),
+            ExitCode::from(EXIT_CRLF)
+        );
+        assert_eq!(
+            check(&["lf", "cr"], LineEnding::LF, true),
+            ExitCode::from(EXIT_CR)
+        );
+        assert_eq!(
+            check(&["crlf", "mixed", "cr"], LineEnding::LF, true),
+            ExitCode::from(EXIT_MIXED)
+        );
+        assert_eq!(check(&["mixed"], LineEnding::LF, false), ExitCode::FAILURE);
+    }
+
+    #[test]
+    fn test_check_only() {
+        let dir = TestDir::new("check-only");
+        let f = dir.join("a.txt");
+        let check_only = |content: &[u8], options: &[&str]| {
+            std::fs::write(&f, content).unwrap();
+            let args = parse(&[&["set-lf", "-q", "--check-only"], options].concat());
+            let code = set_files(std::slice::from_ref(&f), &args).unwrap();
+            assert_eq!(std::fs::read(&f).unwrap(), content, "never written");
+            code
+        };
+        assert_eq!(check_only(b"a\r\n", &[]), ExitCode::FAILURE);
+        assert_eq!(check_only(b"a\n", &[]), ExitCode::SUCCESS);
+        // every option changing the content counts
+        assert_eq!(check_only(b"a \n", &[]), ExitCode::SUCCESS);
+        assert_eq!(
+            check_only(b"a \n", &["--strip-trailing-ws"]),
+            ExitCode::FAILURE
+        );
+    }
+
+    #[test]
+    fn test_stream_files() {
+        let dir = TestDir::new("stream");
+        std::fs::create_dir_all(dir.join(".git")).unwrap();
+        std::fs::create_dir_all(dir.join("sub").join("deep")).unwrap();
+        std::fs::write(dir.join(".gitignore"), "*.log\n").unwrap();
+        for f in ["a.txt", "b.log", "sub/c.txt", "sub/d.bak", "sub/deep/e.txt"] {
+            std::fs::write(dir.join(f), "a\n").unwrap();
+        }
+        let all = dir.join("**").join("*").display().to_string();
+        let sub = dir.join("sub").display().to_string();
+        let a = dir.join("a.txt").display().to_string();
+        let exclude = format!("{}/**/*.bak", dir.display());
+        for options in [
+            vec!["--respect-gitignore", "--exclude", &exclude],
+            vec!["--max-depth", "1", "--include-hidden"],
+        ] {
+            let args = parse(&[&["measure", &all, &sub, &a][..], &options].concat());
+            let match_options = discovery::match_options(args.ignore_case, args.include_hidden);
+            let listed = find_files(&args, match_options).unwrap();
+            let walked: Vec<PathBuf> = walk_files(&args, match_options)
+                .unwrap()
+                .collect::<anyhow::Result<_>>()
+                .unwrap();
+            assert!(!listed.is_empty());
+            assert_eq!(walked, listed);
+        }
+    }
+
+    #[test]
+    fn test_set_skips_gzip() {
+        use flate2::{write::GzEncoder, Compression};
+
+        let dir = TestDir::new("gzip");
+        let mut gz = GzEncoder::new(vec![], Compression::default());
+        gz.write_all(b"a\r\nb\r\n").unwrap();
+        let gz = gz.finish().unwrap();
+        std::fs::write(dir.join("a.txt.gz"), &gz).unwrap();
+        std::fs::write(dir.join("b.txt"), b"a\r\n").unwrap();
+
+        let pattern = dir.display().to_string();
+        for options in [&[][..], &["--force-binary"], &["--sniff"]] {
+            let args = parse(&[&["set-lf", "-q", &pattern][..], options].concat());
+            let files = find_files(&args, discovery::match_options(false, false)).unwrap();
+            assert_eq!(files.len(), 2);
+            assert_eq!(set_files(&files, &args).unwrap(), ExitCode::SUCCESS);
+            assert_eq!(std::fs::read(dir.join("a.txt.gz")).unwrap(), gz);
+            assert_eq!(std::fs::read(dir.join("b.txt")).unwrap(), b"a\n");
+        }
+        let args = parse(&["check-lf", "-q", &pattern]);
+        let files = find_files(&args, discovery::match_options(false, false)).unwrap();
+        assert_eq!(
+            check_files(&files, &args, LineEnding::LF).unwrap(),
+            ExitCode::FAILURE
+        );
+    }
+
+    #[test]
+    fn test_filter_streams() {
+        /// Fails every read, like a pipe closed by the other side
+        struct Broken;
+        impl Read for Broken {
+            fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+                Err(std::io::ErrorKind::BrokenPipe.into())
+            }
+        }
+        let input = b"a\r\n".repeat(10_000);
+
+        // converted as it's read, so what came before the failure is out already
+        let mut dest = vec![];
+        let source = BufReader::new(input.as_slice().chain(Broken));
+        assert!(filter(source, &mut dest, &parse(&["set-lf"])).is_err());
+        assert!(dest.len() > BINARY_SNIFF_LEN);
+        assert!(dest.starts_with(b"a\na\n"));
+
+        let mut dest = vec![];
+        filter(input.as_slice(), &mut dest, &parse(&["set", "lf"])).unwrap();
+        assert_eq!(dest, b"a\n".repeat(10_000));
+        let mut dest = vec![];
+        filter(b"a\r\nb\n\n".as_slice(), &mut dest, &parse(&["normalize"])).unwrap();
+        assert_eq!(dest, b"a\nb\n\n");
+    }
 }
@@ -0,0 +1,38 @@
+//! Temporary directory of a test, shared by the tests of the library and the binary
+
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+/// Directory named after the test and the process, removed when dropped,
+/// so a failed assertion doesn't leave it behind
+pub struct TestDir(PathBuf);
+
+impl TestDir {
+    pub fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("crlf-test-{name}-{}", std::process::id()));
+        // left over by a run killed before dropping it
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+}
+
+impl Deref for TestDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TestDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,500 @@
+//! Line endings of UTF-16 text
+//!
+//! The byte oriented functions in the crate root misread UTF-16, where every
+//! CR and LF is a 16-bit code unit with a NUL byte next to it.
+//! The byte order has to be known up front, [`detect_bom`] can find it from
+//! a byte order mark. UTF-16 without a BOM is only handled when the caller
+//! passes the byte order explicitly.
+
+use std::io::{BufRead, BufWriter, ErrorKind, Write};
+
+use crate::{Bom, ConvertOptions, CrlfStat, LineEnding, LoneCr, MeasureOptions};
+
+const CR: u16 = 0x000D;
+const LF: u16 = 0x000A;
+const BOM: u16 = 0xFEFF;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteOrder {
+    LittleEndian,
+    BigEndian,
+}
+
+impl ByteOrder {
+    fn unit(self, bytes: [u8; 2]) -> u16 {
+        match self {
+            ByteOrder::LittleEndian => u16::from_le_bytes(bytes),
+            ByteOrder::BigEndian => u16::from_be_bytes(bytes),
+        }
+    }
+
+    fn bytes(self, unit: u16) -> [u8; 2] {
+        match self {
+            ByteOrder::LittleEndian => unit.to_le_bytes(),
+            ByteOrder::BigEndian => unit.to_be_bytes(),
+        }
+    }
+}
+
+/// Byte order given by a leading UTF-16 byte order mark
+pub fn detect_bom(sample: &[u8]) -> Option<ByteOrder> {
+    match sample {
+        [0xFF, 0xFE, ..] => Some(ByteOrder::LittleEndian),
+        [0xFE, 0xFF, ..] => Some(ByteOrder::BigEndian),
+        _ => None,
+    }
+}
+
+/// Feed every code unit of `source` to `f`
+///
+/// A unit split between two reads is carried over to the next one.
+fn for_each_unit<R, F>(mut source: R, order: ByteOrder, mut f: F) -> std::io::Result<()>
+where
+    R: BufRead,
+    F: FnMut(u16) -> std::io::Result<()>,
+{
+    let mut carry = None;
+    loop {
+        let available = match source.fill_buf() {
+            Ok(available) => available,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        if available.is_empty() {
+            break;
+        }
+        let len = available.len();
+        let mut bytes = available;
+        if let Some(first) = carry.take() {
+            f(order.unit([first, bytes[0]]))?;
+            bytes = &bytes[1..];
+        }
+        let mut chunks = bytes.chunks_exact(2);
+        for chunk in &mut chunks {
+            f(order.unit([chunk[0], chunk[1]]))?;
+        }
+        carry = chunks.remainder().first().copied();
+        source.consume(len);
+    }
+    if carry.is_some() {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidData,
+            "UTF-16 input has an odd number of bytes",
+        ));
+    }
+    Ok(())
+}
+
+/// UTF-16 counterpart of [`CrlfStat::measure_file`]
+pub fn measure_file<R: BufRead>(source: R, order: ByteOrder) -> std::io::Result<CrlfStat> {
+    measure_with(source, order, &MeasureOptions::default())
+}
+
+/// UTF-16 counterpart of [`CrlfStat::measure_with`]
+pub fn measure_with<R: BufRead>(
+    source: R,
+    order: ByteOrder,
+    options: &MeasureOptions,
+) -> std::io::Result<CrlfStat> {
+    let mut stat = CrlfStat::default();
+    let mut first_unit = true;
+    let mut after_cr = false;
+    let mut last_unit = None;
+    // whether nothing but a line ending or the BOM was seen on the current line
+    let mut blank = true;
+    // byte offset of the current unit
+    let mut offset = 0;
+    for_each_unit(source, order, |unit| {
+        let bom = first_unit && unit == BOM;
+        if first_unit {
+            stat.bom = bom;
+            first_unit = false;
+        }
+        if after_cr || unit == LF {
+            stat.blank_lines += usize::from(options.blank_lines && blank);
+            blank = true;
+        }
+        match unit {
+            LF if after_cr => stat.record_at(Some(LineEnding::CRLF), offset - 2, options),
+            _ if after_cr => stat.record_at(Some(LineEnding::CR), offset - 2, options),
+            LF => stat.record_at(Some(LineEnding::LF), offset, options),
+            _ => {}
+        }
+        blank &= bom || unit == CR || unit == LF;
+        after_cr = unit == CR;
+        last_unit = Some(unit);
+        offset += 2;
+        Ok(())
+    })?;
+    if after_cr {
+        stat.blank_lines += usize::from(options.blank_lines && blank);
+        stat.record_at(Some(LineEnding::CR), offset - 2, options);
+    } else if last_unit.is_some_and(|unit| unit != LF) {
+        stat.record(None, options);
+    }
+    Ok(stat)
+}
+
+/// UTF-16 counterpart of [`crate::convert_to`]
+pub fn convert_to<R: BufRead, W: Write>(
+    source: R,
+    dest: W,
+    order: ByteOrder,
+    ending: LineEnding,
+) -> std::io::Result<CrlfStat> {
+    convert_with(source, dest, order, &ConvertOptions::new(ending))
+}
+
+/// UTF-16 counterpart of [`crate::convert_with`]
+pub fn convert_with<R: BufRead, W: Write>(
+    source: R,
+    dest: W,
+    order: ByteOrder,
+    options: &ConvertOptions,
+) -> std::io::Result<CrlfStat> {
+    let mut converter = Converter {
+        dest: BufWriter::new(dest),
+        order,
+        options,
+        stat: CrlfStat::default(),
+        line: vec![],
+        first_line: true,
+        last_terminated: true,
+        line_number: 0,
+        continued: false,
+    };
+    let mut after_cr = false;
+    for_each_unit(source, order, |unit| {
+        if after_cr {
+            after_cr = false;
+            if unit == LF {
+                return converter.write_line(Some(LineEnding::CRLF));
+            }
+            converter.write_line(Some(LineEnding::CR))?;
+        }
+        match unit {
+            CR => after_cr = true,
+            LF => converter.write_line(Some(LineEnding::LF))?,
+            _ => converter.line.push(unit),
+        }
+        Ok(())
+    })?;
+    if after_cr {
+        converter.write_line(Some(LineEnding::CR))?;
+    } else if !converter.line.is_empty() {
+        converter.write_line(None)?;
+    }
+    if options.ensure_final_newline && !converter.last_terminated {
+        converter.write_ending()?;
+    }
+    converter.dest.flush()?;
+    Ok(converter.stat)
+}
+
+struct Converter<'a, W: Write> {
+    dest: BufWriter<W>,
+    order: ByteOrder,
+    options: &'a ConvertOptions,
+    /// Line endings seen in the source
+    stat: CrlfStat,
+    /// Content of the current line, without terminator
+    line: Vec<u16>,
+    first_line: bool,
+    last_terminated: bool,
+    /// 1-based number of the current line, a lone CR that isn't an ending doesn't end it
+    line_number: usize,
+    /// Whether the last piece written ended with such a CR, so the line goes on
+    continued: bool,
+}
+
+impl<W: Write> Converter<'_, W> {
+    /// Write out the current line, which was terminated by `ending` in the source
+    fn write_line(&mut self, ending: Option<LineEnding>) -> std::io::Result<()> {
+        self.stat.record(ending, &MeasureOptions::default());
+        let terminated = ending.is_some_and(|e| self.options.ends_line(e));
+        let mut content = self.line.as_slice();
+        let continuation = self.continued;
+        if !continuation {
+            self.line_number += 1;
+        }
+        self.continued = ending.is_some() && !terminated;
+        let converts = self.options.converts_line(self.line_number);
+        if self.first_line {
+            if let Some(rest) = content.strip_prefix(&[BOM]) {
+                self.stat.bom = true;
+                content = rest;
+                if self.options.bom != Bom::Strip || !converts {
+                    self.dest.write_all(&self.order.bytes(BOM))?;
+                }
+            } else if self.options.bom == Bom::Add && converts {
+                self.dest.write_all(&self.order.bytes(BOM))?;
+            }
+            self.first_line = false;
+        }
+        if !converts {
+            for unit in content {
+                self.dest.write_all(&self.order.bytes(*unit))?;
+            }
+            if let Some(ending) = ending {
+                write_ascii(&mut self.dest, self.order, ending.as_bytes())?;
+            }
+            self.last_terminated = true;
+            self.line.clear();
+            return Ok(());
+        }
+        if self.options.strip_trailing_ws && !self.continued {
+            let len = content
+                .iter()
+                .rposition(|c| *c != u16::from(b' ') && *c != u16::from(b'\t'))
+                .map_or(0, |idx| idx + 1);
+            content = &content[..len];
+        }
+        if let Some(tab_width) = self.options.indent.tab_width().filter(|_| !continuation) {
+            let (width, len) = crate::indentation(content, tab_width);
+            for unit in self.options.indent.units(width) {
+                self.dest.write_all(&self.order.bytes(u16::from(unit)))?;
+            }
+            content = &content[len..];
+        }
+        for unit in content {
+            self.dest.write_all(&self.order.bytes(*unit))?;
+        }
+        if terminated {
+            self.write_ending()?;
+        } else if ending == Some(LineEnding::CR) && self.options.lone_cr == LoneCr::Preserve {
+            self.dest.write_all(&self.order.bytes(CR))?;
+        }
+        self.last_terminated = terminated;
+        self.line.clear();
+        Ok(())
+    }
+
+    fn write_ending(&mut self) -> std::io::Result<()> {
+        write_ascii(&mut self.dest, self.order, self.options.ending.as_bytes())
+    }
+}
+
+/// Write the ASCII `bytes` as UTF-16 units
+fn write_ascii<W: Write>(dest: &mut W, order: ByteOrder, bytes: &[u8]) -> std::io::Result<()> {
+    for byte in bytes {
+        dest.write_all(&order.bytes(u16::from(*byte)))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufReader, Cursor};
+
+    use super::*;
+    use crate::Indent;
+
+    fn encode(text: &str, order: ByteOrder) -> Vec<u8> {
+        text.encode_utf16().flat_map(|u| order.bytes(u)).collect()
+    }
+
+    #[test]
+    fn test_detect_bom() {
+        assert_eq!(
+            detect_bom(&encode("\u{FEFF}a", ByteOrder::LittleEndian)),
+            Some(ByteOrder::LittleEndian)
+        );
+        assert_eq!(
+            detect_bom(&encode("\u{FEFF}a", ByteOrder::BigEndian)),
+            Some(ByteOrder::BigEndian)
+        );
+        assert_eq!(detect_bom(b"\xEF\xBB\xBFa"), None);
+        assert_eq!(detect_bom(b"\xFF"), None);
+    }
+
+    #[test]
+    fn test_measure() {
+        let lf_file = std::fs::read_to_string("test/Cargo.toml.lf").unwrap();
+        let mixed_file = std::fs::read_to_string("test/Cargo.toml.mixed").unwrap();
+        for order in [ByteOrder::LittleEndian, ByteOrder::BigEndian] {
+            let input = encode(&format!("\u{FEFF}{lf_file}"), order);
+            let stat = measure_file(Cursor::new(input), order).unwrap();
+            assert_eq!(stat.is_pure(), Some(LineEnding::LF));
+            assert_eq!(stat.lf(), 14);
+            assert!(stat.has_bom());
+            assert!(stat.ends_with_newline());
+
+            let stat = measure_file(Cursor::new(encode(&mixed_file, order)), order).unwrap();
+            assert_eq!(stat.crlf(), 8);
+            assert_eq!(stat.lf(), 6);
+            assert!(!stat.has_bom());
+
+            let stat =
+                measure_file(Cursor::new(encode("a\rb\r\n\u{0A0D}\r", order)), order).unwrap();
+            assert_eq!(stat.cr(), 2);
+            assert_eq!(stat.crlf(), 1);
+            assert_eq!(stat.lf(), 0);
+            assert_eq!(stat.interior_cr(), 1);
+
+            let input = encode("\u{FEFF}\r\n\na\n \n\r\r\nb", order);
+            let options = MeasureOptions::new().blank_lines(true);
+            let stat = measure_with(Cursor::new(input), order, &options).unwrap();
+            assert_eq!(stat.blank_lines(), 4);
+            assert_eq!(stat.lines, 7);
+            let options = MeasureOptions::new().record_offsets(true);
+            let input = encode("a\r\nb\nc\r\n\rd\r", order);
+            let stat = measure_with(Cursor::new(input), order, &options).unwrap();
+            assert_eq!(stat.offsets(LineEnding::CRLF), [2, 12]);
+            assert_eq!(stat.offsets(LineEnding::LF), [8]);
+            assert_eq!(stat.offsets(LineEnding::CR), [16, 20]);
+
+            let stat = measure_file(Cursor::new(encode("\u{FEFF}", order)), order).unwrap();
+            assert_eq!(stat.lines, 1);
+        }
+
+        assert!(measure_file(Cursor::new(b"a\0b"), ByteOrder::LittleEndian).is_err());
+    }
+
+    #[test]
+    fn test_split_reads() {
+        let mixed_file = std::fs::read_to_string("test/Cargo.toml.mixed").unwrap();
+        let crlf_file = std::fs::read_to_string("test/Cargo.toml.crlf").unwrap();
+        for order in [ByteOrder::LittleEndian, ByteOrder::BigEndian] {
+            for text in [format!("\u{FEFF}{mixed_file}"), "a\r\r\n\n\r".to_string()] {
+                let input = encode(&text, order);
+                let expected = measure_file(Cursor::new(&input), order).unwrap();
+                // an odd size splits every other unit, and a CRLF, between two reads
+                for size in [1, 3, 5] {
+                    let source = BufReader::with_capacity(size, Cursor::new(&input));
+                    let stat = measure_file(source, order).unwrap();
+                    assert_eq!(stat.endings, expected.endings);
+                    assert_eq!(stat.lines, expected.lines);
+                    assert_eq!(stat.bom, expected.bom);
+                    assert_eq!(stat.final_newline, expected.final_newline);
+                }
+            }
+
+            let input = encode(&mixed_file, order);
+            for size in [1, 3] {
+                let mut dest = vec![];
+                let source = BufReader::with_capacity(size, Cursor::new(&input));
+                let stat = convert_to(source, &mut dest, order, LineEnding::CRLF).unwrap();
+                assert_eq!(stat.crlf(), 8);
+                assert_eq!(stat.lf(), 6);
+                assert_eq!(dest, encode(&crlf_file, order));
+            }
+        }
+    }
+
+    #[test]
+    fn test_convert() {
+        let lf_file = std::fs::read_to_string("test/Cargo.toml.lf").unwrap();
+        let crlf_file = std::fs::read_to_string("test/Cargo.toml.crlf").unwrap();
+        let mixed_file = std::fs::read_to_string("test/Cargo.toml.mixed").unwrap();
+        for order in [ByteOrder::LittleEndian, ByteOrder::BigEndian] {
+            let mut dest = vec![];
+            convert_to(
+                Cursor::new(encode(&mixed_file, order)),
+                &mut dest,
+                order,
+                LineEnding::CRLF,
+            )
+            .unwrap();
+            assert_eq!(dest, encode(&crlf_file, order));
+
+            let mut dest = vec![];
+            convert_to(
+                Cursor::new(encode(&crlf_file, order)),
+                &mut dest,
+                order,
+                LineEnding::LF,
+            )
+            .unwrap();
+            assert_eq!(dest, encode(&lf_file, order));
+
+            let options = ConvertOptions::new(LineEnding::LF)
+                .strip_bom(true)
+                .strip_trailing_ws(true)
+                .ensure_final_newline(true);
+            let mut dest = vec![];
+            convert_with(
+                Cursor::new(encode("\u{FEFF}a \r\r\n\u{0A0D}\t", order)),
+                &mut dest,
+                order,
+                &options,
+            )
+            .unwrap();
+            assert_eq!(dest, encode("a\n\n\u{0A0D}\n", order));
+
+            let mut dest = vec![];
+            let options = ConvertOptions::new(LineEnding::LF).bom(Bom::Add);
+            convert_with(
+                Cursor::new(encode("a\r\n", order)),
+                &mut dest,
+                order,
+                &options,
+            )
+            .unwrap();
+            assert_eq!(dest, encode("\u{FEFF}a\n", order));
+
+            let mut dest = vec![];
+            let options = ConvertOptions::new(LineEnding::CRLF).lone_cr(LoneCr::Preserve);
+            convert_with(
+                Cursor::new(encode("a\rb\n", order)),
+                &mut dest,
+                order,
+                &options,
+            )
+            .unwrap();
+            assert_eq!(dest, encode("a\rb\r\n", order));
+
+            let mut dest = vec![];
+            let options = ConvertOptions::new(LineEnding::LF).indent(Indent::Spaces(4));
+            convert_with(
+                Cursor::new(encode("\u{FEFF}\ta\tb\r\n  \tc", order)),
+                &mut dest,
+                order,
+                &options,
+            )
+            .unwrap();
+            assert_eq!(dest, encode("\u{FEFF}    a\tb\n    c", order));
+
+            let mut dest = vec![];
+            let options = ConvertOptions::new(LineEnding::LF)
+                .strip_bom(true)
+                .lines(2..=3);
+            convert_with(
+                Cursor::new(encode("\u{FEFF}a\r\nb\r\nc\rd\r\n", order)),
+                &mut dest,
+                order,
+                &options,
+            )
+            .unwrap();
+            assert_eq!(dest, encode("\u{FEFF}a\r\nb\nc\nd\r\n", order));
+
+            let stat = convert_to(
+                Cursor::new(encode(&mixed_file, order)),
+                std::io::sink(),
+                order,
+                LineEnding::LF,
+            )
+            .unwrap();
+            assert_eq!(stat.crlf(), 8);
+            assert_eq!(stat.lf(), 6);
+        }
+    }
+
+    #[test]
+    fn test_lone_cr_in_line() {
+        for order in [ByteOrder::LittleEndian, ByteOrder::BigEndian] {
+            let options = ConvertOptions::new(LineEnding::CRLF)
+                .lone_cr(LoneCr::Preserve)
+                .strip_trailing_ws(true)
+                .lines(2..=2);
+            let mut dest = vec![];
+            convert_with(
+                Cursor::new(encode("a \r b \nc \rd \ne \n", order)),
+                &mut dest,
+                order,
+                &options,
+            )
+            .unwrap();
+            assert_eq!(dest, encode("a \r b \nc \rd\r\ne \n", order));
+        }
+    }
+}